@@ -53,6 +53,8 @@ const METRICS_PROMETHEUS: &str = "prometheus";
 const METRICS_ROCKSDB_KV: &str = "rocksdb_kv";
 const METRICS_ROCKSDB_RAFT: &str = "rocksdb_raft";
 const METRICS_JEMALLOC: &str = "jemalloc";
+// How many regions `recreate-regions` writes per kv/raft batch flush.
+const RECREATE_REGIONS_BATCH_SIZE: usize = 256;
 
 type MvccInfoStream = Pin<Box<dyn Stream<Item = Result<(Vec<u8>, MvccInfo), String>>>>;
 
@@ -518,6 +520,10 @@ trait DebugExecutor {
     /// Recreate the region with metadata from pd, but alloc new id for it.
     fn recreate_region(&self, sec_mgr: Arc<SecurityManager>, pd_cfg: &PdConfig, region_id: u64);
 
+    /// Bulk version of `recreate_region`: recreates many regions with
+    /// metadata from pd at once, each with a freshly allocated id.
+    fn recreate_regions(&self, sec_mgr: Arc<SecurityManager>, pd_cfg: &PdConfig, region_ids: Vec<u64>);
+
     fn check_region_consistency(&self, _: u64);
 
     fn check_local_mode(&self);
@@ -749,6 +755,10 @@ impl DebugExecutor for DebugClient {
         self.check_local_mode();
     }
 
+    fn recreate_regions(&self, _: Arc<SecurityManager>, _: &PdConfig, _: Vec<u64>) {
+        self.check_local_mode();
+    }
+
     fn check_region_consistency(&self, region_id: u64) {
         let mut req = RegionConsistencyCheckRequest::default();
         req.set_region_id(region_id);
@@ -969,6 +979,53 @@ impl<ER: RaftEngine> DebugExecutor for Debugger<ER> {
         v1!("success");
     }
 
+    fn recreate_regions(&self, mgr: Arc<SecurityManager>, pd_cfg: &PdConfig, region_ids: Vec<u64>) {
+        let rpc_client = RpcClient::new(pd_cfg, None, mgr)
+            .unwrap_or_else(|e| perror_and_exit("RpcClient::new", e));
+        let store_id = self.get_store_id().expect("get store id");
+
+        let mut regions = Vec::with_capacity(region_ids.len());
+        for region_id in region_ids {
+            let mut region = match block_on(rpc_client.get_region_by_id(region_id)) {
+                Ok(Some(region)) => region,
+                Ok(None) => {
+                    ve1!("no such region {} on PD", region_id);
+                    process::exit(-1)
+                }
+                Err(e) => perror_and_exit("RpcClient::get_region_by_id", e),
+            };
+
+            let new_region_id = rpc_client
+                .alloc_id()
+                .unwrap_or_else(|e| perror_and_exit("RpcClient::alloc_id", e));
+            let new_peer_id = rpc_client
+                .alloc_id()
+                .unwrap_or_else(|e| perror_and_exit("RpcClient::alloc_id", e));
+
+            region.set_id(new_region_id);
+            let old_version = region.get_region_epoch().get_version();
+            region.mut_region_epoch().set_version(old_version + 1);
+            region.mut_region_epoch().set_conf_ver(INIT_EPOCH_CONF_VER);
+
+            region.peers.clear();
+            let mut peer = Peer::default();
+            peer.set_id(new_peer_id);
+            peer.set_store_id(store_id);
+            region.mut_peers().push(peer);
+
+            v1!(
+                "initing empty region {} with peer_id {}...",
+                new_region_id,
+                new_peer_id
+            );
+            regions.push(region);
+        }
+
+        self.recreate_regions(regions, RECREATE_REGIONS_BATCH_SIZE)
+            .unwrap_or_else(|e| perror_and_exit("Debugger::recreate_regions", e));
+        v1!("success");
+    }
+
     fn dump_metrics(&self, _tags: Vec<&str>) {
         unimplemented!("only available for online mode");
     }
@@ -1569,6 +1626,32 @@ fn main() {
                         .help("The origin region id"),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("recreate-regions")
+                .about("Recreate many regions with given metadata at once, allocating new ids for them")
+                .arg(
+                    Arg::with_name("pd")
+                        .required(true)
+                        .short("p")
+                        .takes_value(true)
+                        .multiple(true)
+                        .use_delimiter(true)
+                        .require_delimiter(true)
+                        .value_delimiter(",")
+                        .help("PD endpoints"),
+                )
+                .arg(
+                    Arg::with_name("regions")
+                        .required(true)
+                        .short("r")
+                        .takes_value(true)
+                        .multiple(true)
+                        .use_delimiter(true)
+                        .require_delimiter(true)
+                        .value_delimiter(",")
+                        .help("The origin region ids"),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("metrics")
                 .about("Print the metrics")
@@ -2165,6 +2248,22 @@ fn main() {
         };
         let region_id = matches.value_of("region").unwrap().parse().unwrap();
         debug_executor.recreate_region(mgr, &pd_cfg, region_id);
+    } else if let Some(matches) = matches.subcommand_matches("recreate-regions") {
+        let pd_cfg = PdConfig {
+            endpoints: matches
+                .values_of("pd")
+                .unwrap()
+                .map(ToOwned::to_owned)
+                .collect(),
+            ..Default::default()
+        };
+        let region_ids = matches
+            .values_of("regions")
+            .unwrap()
+            .map(str::parse)
+            .collect::<Result<Vec<_>, _>>()
+            .expect("parse regions fail");
+        debug_executor.recreate_regions(mgr, &pd_cfg, region_ids);
     } else if let Some(matches) = matches.subcommand_matches("consistency-check") {
         let region_id = matches.value_of("region").unwrap().parse().unwrap();
         debug_executor.check_region_consistency(region_id);
@@ -663,7 +663,7 @@ impl<E: Engine, R: RegionInfoProvider + Clone + 'static> Endpoint<E, R> {
         // TODO: make it async.
         self.pool.borrow_mut().spawn(move || {
             tikv_alloc::add_thread_memory_accessor();
-            let _with_io_type = WithIOType::new(IOType::Export);
+            let _with_io_type = WithIOType::new(IOType::Backup);
             defer!({
                 tikv_alloc::remove_thread_memory_accessor();
             });
@@ -1309,8 +1309,8 @@ pub mod tests {
             );
             let (none, _rx) = block_on(rx.into_future());
             assert!(none.is_none(), "{:?}", none);
-            assert_eq!(stats.fetch(IOType::Export, IOOp::Write), 0);
-            assert_ne!(stats.fetch(IOType::Export, IOOp::Read), 0);
+            assert_eq!(stats.fetch(IOType::Backup, IOOp::Write), 0);
+            assert_ne!(stats.fetch(IOType::Backup, IOOp::Read), 0);
         }
     }
 
@@ -12,5 +12,5 @@ pub mod test_runner;
 pub use self::batch::{create_system, BatchRouter, BatchSystem, HandlerBuilder, PollHandler};
 pub use self::config::Config;
 pub use self::fsm::Fsm;
-pub use self::mailbox::{BasicMailbox, Mailbox};
+pub use self::mailbox::{BasicMailbox, Mailbox, PendingSummary};
 pub use self::router::Router;
@@ -1,11 +1,34 @@
 // Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
 
 use crate::fsm::{Fsm, FsmScheduler, FsmState};
+#[cfg(feature = "subsystem-attribution")]
+use collections::HashMap;
 use crossbeam::channel::{SendError, TrySendError};
 use std::borrow::Cow;
+#[cfg(feature = "subsystem-attribution")]
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tikv_util::mpsc;
 
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// A coarse, non-destructive snapshot of a mailbox's backlog, returned by
+/// [`BasicMailbox::pending_summary`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PendingSummary {
+    /// Number of messages currently queued for the owner.
+    pub len: usize,
+    /// How long it's been since a message was last sent to this mailbox.
+    pub idle_duration: Duration,
+}
+
 /// A basic mailbox.
 ///
 /// Every mailbox should have one and only one owner, who will receive all
@@ -17,6 +40,18 @@ use tikv_util::mpsc;
 pub struct BasicMailbox<Owner: Fsm> {
     sender: mpsc::LooseBoundedSender<Owner::Message>,
     state: Arc<FsmState<Owner>>,
+    // Timestamp (in milliseconds since UNIX_EPOCH) of the last message sent
+    // to this mailbox. Used to detect mailboxes of quiescent regions so
+    // callers can decide whether it's worth reclaiming their resources.
+    last_active_ms: Arc<AtomicU64>,
+    // See `set_recovery_target`. Defaults to `false`.
+    recovery: Arc<AtomicBool>,
+    // Per-subsystem send counts, keyed by the tag passed to
+    // `force_send_tagged`/`try_send_tagged`. Only present when the
+    // `subsystem-attribution` feature is enabled, so a mailbox pays nothing
+    // for it otherwise.
+    #[cfg(feature = "subsystem-attribution")]
+    tag_send_counts: Arc<Mutex<HashMap<&'static str, u64>>>,
 }
 
 impl<Owner: Fsm> BasicMailbox<Owner> {
@@ -28,9 +63,79 @@ impl<Owner: Fsm> BasicMailbox<Owner> {
         BasicMailbox {
             sender,
             state: Arc::new(FsmState::new(fsm)),
+            last_active_ms: Arc::new(AtomicU64::new(now_millis())),
+            recovery: Arc::new(AtomicBool::new(false)),
+            #[cfg(feature = "subsystem-attribution")]
+            tag_send_counts: Arc::new(Mutex::new(HashMap::default())),
         }
     }
 
+    /// Flags this mailbox as a coordinated-recovery target, so a
+    /// busy-skipping broadcast (e.g. `Router::broadcast_skip_busy`) still
+    /// delivers control-plane messages to it instead of skipping it the way
+    /// it would any other backlogged mailbox. Cleared by passing `false`.
+    #[inline]
+    pub fn set_recovery_target(&self, recovery: bool) {
+        self.recovery.store(recovery, Ordering::Relaxed);
+    }
+
+    /// Returns whether this mailbox is currently flagged as a
+    /// coordinated-recovery target. See `set_recovery_target`.
+    #[inline]
+    pub fn is_recovery_target(&self) -> bool {
+        self.recovery.load(Ordering::Relaxed)
+    }
+
+    /// STATUS: infeasible as scoped, flagged for the backlog owner. The
+    /// originating request asked for an idle mailbox to actually release its
+    /// command channel's buffer and reallocate lazily on the next send, to
+    /// cut the memory `test_many_regions` attributes to quiescent mailboxes.
+    /// Two things stand in the way of doing that for real:
+    /// - `sender` here is a [`mpsc::LooseBoundedSender`], created by
+    ///   [`mpsc::loose_bounded`] on top of `mpsc::unbounded`, which is itself
+    ///   crossbeam's unbounded channel: a linked list of fixed-size segments
+    ///   that are freed as they're drained, not a single buffer sized to
+    ///   capacity. An idle, empty mailbox's channel is already down to
+    ///   whatever a handful of empty segments cost -- there's no larger
+    ///   reservation sitting around to release.
+    /// - Even if there were, `BasicMailbox` only holds the sending half of
+    ///   the channel; the receiving half is owned by the concrete `Fsm`
+    ///   (see `pending_summary`'s doc comment), and other threads may hold
+    ///   their own clones of `sender`. Swapping in a fresh channel on the
+    ///   next send would mean handing the `Fsm` a new receiver and
+    ///   invalidating every outstanding sender clone at the same instant --
+    ///   neither of which this type has a way to do by itself.
+    ///
+    /// What's here instead is the idle-detection half of the request:
+    /// `idle_duration`/`is_idle` (and `Router::idle_mailboxes`, built on top)
+    /// let a caller identify quiescent mailboxes. Recommend re-scoping the
+    /// request to a real caller that acts on that list (e.g. one that closes
+    /// and later recreates the whole mailbox, which the router already knows
+    /// how to do via `close`), rather than a same-mailbox buffer swap that
+    /// the channel and ownership model here don't support.
+    ///
+    /// Returns how long this mailbox has gone without a message being sent
+    /// to it.
+    #[inline]
+    pub fn idle_duration(&self) -> Duration {
+        let last = self.last_active_ms.load(Ordering::Relaxed);
+        Duration::from_millis(now_millis().saturating_sub(last))
+    }
+
+    /// Returns whether this mailbox has been idle for at least `idle_after`,
+    /// i.e. it is a candidate for having its channel resources reclaimed by
+    /// an idle-close policy. See `idle_duration`'s doc comment for why that
+    /// reclaim isn't implemented here.
+    #[inline]
+    pub fn is_idle(&self, idle_after: Duration) -> bool {
+        self.idle_duration() >= idle_after
+    }
+
+    #[inline]
+    fn touch(&self) {
+        self.last_active_ms.store(now_millis(), Ordering::Relaxed);
+    }
+
     pub(crate) fn is_connected(&self) -> bool {
         self.sender.is_sender_connected()
     }
@@ -43,6 +148,30 @@ impl<Owner: Fsm> BasicMailbox<Owner> {
         self.state.take_fsm()
     }
 
+    /// Atomically exchanges the fsm behind this mailbox for `new_fsm`,
+    /// returning the old one. Messages already queued in `sender` are
+    /// untouched by the swap, so anything sent before or during the call
+    /// stays queued for whichever fsm ends up installed and is delivered to
+    /// it once it's next scheduled.
+    ///
+    /// Meant for recovery paths that need to hot-swap a region's fsm (e.g.
+    /// after replaying a snapshot into a fresh peer) without losing whatever
+    /// was already sitting in the mailbox.
+    ///
+    /// Returns `None` without installing `new_fsm` if the old fsm is
+    /// currently checked out for polling by a worker thread, the same
+    /// condition under which `take_fsm` itself returns `None`. There's no
+    /// safe way to make this unconditional: the fsm being polled elsewhere
+    /// can't be seized without either racing that worker or blocking until
+    /// it finishes, and this call has no way to distinguish "will finish
+    /// shortly" from "stuck forever". Callers that must swap unconditionally
+    /// should retry after observing the mailbox is no longer busy.
+    pub fn swap_fsm(&self, new_fsm: Box<Owner>) -> Option<Box<Owner>> {
+        let old_fsm = self.take_fsm()?;
+        self.release(new_fsm);
+        Some(old_fsm)
+    }
+
     #[inline]
     pub fn len(&self) -> usize {
         self.sender.len()
@@ -53,6 +182,47 @@ impl<Owner: Fsm> BasicMailbox<Owner> {
         self.sender.is_empty()
     }
 
+    /// Returns whether this mailbox's queue length is at or over its channel
+    /// capacity. `try_send` checks that limit only once every few attempts,
+    /// so it can still accept a message while this returns true. Meant for
+    /// surfacing regions under write-stall-style backlog to operators.
+    #[inline]
+    pub fn is_busy(&self) -> bool {
+        self.sender.is_busy()
+    }
+
+    /// STATUS: infeasible as scoped, flagged for the backlog owner. The
+    /// originating request asked for `dump_pending(&self) -> Vec<MessageSummary>`,
+    /// draining the channel into a temporary buffer and re-enqueuing (or a
+    /// peek-based iterator) to list each queued message's type and key
+    /// fields. Neither approach is available to this type, and not just
+    /// because draining in place would race the real consumer (which the
+    /// request already anticipated): `BasicMailbox` never holds the
+    /// receiving half of the channel to begin with. `LooseBoundedSender`
+    /// (see [`mpsc::LooseBoundedSender`]) has no peek/iterate capability of
+    /// its own, and the `Receiver` it's paired with is held by the concrete
+    /// `Fsm` (e.g. `PeerFsm::receiver`), not by `BasicMailbox` -- so there is
+    /// no channel here to drain, re-enqueue into, or iterate, however
+    /// carefully. `batch-system` is also generic over `Fsm::Message`, so
+    /// even with receiver access it has no way to know how to summarize an
+    /// arbitrary message's type and key fields.
+    ///
+    /// What's here instead is the coarse, non-destructive part of the
+    /// request that this type actually can answer: how many messages are
+    /// queued and how long the mailbox has been idle. A real
+    /// `dump_pending` has to live in the owner FSM's own message-handling
+    /// loop, which is the one place that both owns the receiver and knows
+    /// the concrete message type. Recommend re-scoping the request to that
+    /// FSM-level loop (e.g. `PeerFsm`/`PeerFsmDelegate` in raftstore) rather
+    /// than `BasicMailbox`.
+    #[inline]
+    pub fn pending_summary(&self) -> PendingSummary {
+        PendingSummary {
+            len: self.sender.len(),
+            idle_duration: self.idle_duration(),
+        }
+    }
+
     /// Force sending a message despite the capacity limit on channel.
     #[inline]
     pub fn force_send<S: FsmScheduler<Fsm = Owner>>(
@@ -61,6 +231,7 @@ impl<Owner: Fsm> BasicMailbox<Owner> {
         scheduler: &S,
     ) -> Result<(), SendError<Owner::Message>> {
         self.sender.force_send(msg)?;
+        self.touch();
         self.state.notify(scheduler, Cow::Borrowed(self));
         Ok(())
     }
@@ -75,10 +246,72 @@ impl<Owner: Fsm> BasicMailbox<Owner> {
         scheduler: &S,
     ) -> Result<(), TrySendError<Owner::Message>> {
         self.sender.try_send(msg)?;
+        self.touch();
         self.state.notify(scheduler, Cow::Borrowed(self));
         Ok(())
     }
 
+    /// Like `force_send`, but returns the mailbox's queue length right
+    /// after the send instead of `()`. `force_send` bypasses the channel's
+    /// capacity limit entirely, so a caller relying on it for critical
+    /// messages otherwise has no signal at all for how deep the backlog it
+    /// just added to actually got; this lets such a caller throttle its own
+    /// subsequent force-sends once the returned length grows too large.
+    #[inline]
+    pub fn force_send_len<S: FsmScheduler<Fsm = Owner>>(
+        &self,
+        msg: Owner::Message,
+        scheduler: &S,
+    ) -> Result<usize, SendError<Owner::Message>> {
+        self.force_send(msg, scheduler)?;
+        Ok(self.len())
+    }
+
+    /// Like `force_send`, but records `tag` -- the caller's subsystem label
+    /// -- against this mailbox's per-tag send counters, queryable via
+    /// `tag_send_counts`. Meant for tracking down which subsystem is the
+    /// source when a mailbox's backlog is under investigation; the plain
+    /// `force_send` remains the allocation-free default for every other
+    /// caller.
+    #[cfg(feature = "subsystem-attribution")]
+    #[inline]
+    pub fn force_send_tagged<S: FsmScheduler<Fsm = Owner>>(
+        &self,
+        msg: Owner::Message,
+        scheduler: &S,
+        tag: &'static str,
+    ) -> Result<(), SendError<Owner::Message>> {
+        self.record_tag(tag);
+        self.force_send(msg, scheduler)
+    }
+
+    /// Like `try_send`, but records `tag` the same way `force_send_tagged`
+    /// does.
+    #[cfg(feature = "subsystem-attribution")]
+    #[inline]
+    pub fn try_send_tagged<S: FsmScheduler<Fsm = Owner>>(
+        &self,
+        msg: Owner::Message,
+        scheduler: &S,
+        tag: &'static str,
+    ) -> Result<(), TrySendError<Owner::Message>> {
+        self.record_tag(tag);
+        self.try_send(msg, scheduler)
+    }
+
+    #[cfg(feature = "subsystem-attribution")]
+    #[inline]
+    fn record_tag(&self, tag: &'static str) {
+        *self.tag_send_counts.lock().unwrap().entry(tag).or_insert(0) += 1;
+    }
+
+    /// Returns the number of tagged sends recorded per subsystem so far. See
+    /// `force_send_tagged`/`try_send_tagged`.
+    #[cfg(feature = "subsystem-attribution")]
+    pub fn tag_send_counts(&self) -> HashMap<&'static str, u64> {
+        self.tag_send_counts.lock().unwrap().clone()
+    }
+
     /// Close the mailbox explicitly.
     #[inline]
     pub(crate) fn close(&self) {
@@ -93,6 +326,10 @@ impl<Owner: Fsm> Clone for BasicMailbox<Owner> {
         BasicMailbox {
             sender: self.sender.clone(),
             state: self.state.clone(),
+            last_active_ms: self.last_active_ms.clone(),
+            recovery: self.recovery.clone(),
+            #[cfg(feature = "subsystem-attribution")]
+            tag_send_counts: self.tag_send_counts.clone(),
         }
     }
 }
@@ -127,4 +364,42 @@ where
     pub fn try_send(&self, msg: Owner::Message) -> Result<(), TrySendError<Owner::Message>> {
         self.mailbox.try_send(msg, &self.scheduler)
     }
+
+    /// Force sending a message despite channel capacity limit, returning
+    /// the resulting queue length. See `BasicMailbox::force_send_len`.
+    #[inline]
+    pub fn force_send_len(&self, msg: Owner::Message) -> Result<usize, SendError<Owner::Message>> {
+        self.mailbox.force_send_len(msg, &self.scheduler)
+    }
+
+    /// Force sending a message despite channel capacity limit, tagged with
+    /// the caller's subsystem. See `BasicMailbox::force_send_tagged`.
+    #[cfg(feature = "subsystem-attribution")]
+    #[inline]
+    pub fn force_send_tagged(
+        &self,
+        msg: Owner::Message,
+        tag: &'static str,
+    ) -> Result<(), SendError<Owner::Message>> {
+        self.mailbox.force_send_tagged(msg, &self.scheduler, tag)
+    }
+
+    /// Try to send a message, tagged with the caller's subsystem. See
+    /// `BasicMailbox::try_send_tagged`.
+    #[cfg(feature = "subsystem-attribution")]
+    #[inline]
+    pub fn try_send_tagged(
+        &self,
+        msg: Owner::Message,
+        tag: &'static str,
+    ) -> Result<(), TrySendError<Owner::Message>> {
+        self.mailbox.try_send_tagged(msg, &self.scheduler, tag)
+    }
+
+    /// Returns this mailbox's per-subsystem tagged send counts. See
+    /// `BasicMailbox::tag_send_counts`.
+    #[cfg(feature = "subsystem-attribution")]
+    pub fn tag_send_counts(&self) -> HashMap<&'static str, u64> {
+        self.mailbox.tag_send_counts()
+    }
 }
@@ -7,6 +7,7 @@ use crossbeam::channel::{SendError, TrySendError};
 use std::cell::Cell;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tikv_util::lru::LruCache;
 use tikv_util::Either;
 use tikv_util::{debug, info};
@@ -252,6 +253,48 @@ where
         }
     }
 
+    /// Like `broadcast_normal`, but visits mailboxes in descending order of
+    /// `order(addr)` instead of arbitrary map order, so a caller that needs
+    /// certain fsms (e.g. those serving metadata during recovery) notified
+    /// ahead of the rest can express that via `order`.
+    ///
+    /// Delivery is still attempted for every mailbox registered when this
+    /// call started, exactly like `broadcast_normal` -- only the order
+    /// attempts happen in changes. Ordering itself is best-effort: mailboxes
+    /// registered, unregistered, or whose priority would change concurrently
+    /// with this call are only reflected up to the snapshot of addresses
+    /// taken at the start, the same way `broadcast_normal` only ever sees a
+    /// snapshot of `self.normals`.
+    pub fn broadcast_ordered(
+        &self,
+        order: impl Fn(u64) -> u32,
+        mut msg_gen: impl FnMut() -> N::Message,
+    ) {
+        let mut addrs: Vec<u64> = { self.normals.lock().unwrap().keys().copied().collect() };
+        addrs.sort_by_key(|addr| std::cmp::Reverse(order(*addr)));
+        for addr in addrs {
+            let _ = self.force_send(addr, msg_gen());
+        }
+    }
+
+    /// Like `broadcast_normal`, but skips any mailbox that's currently busy
+    /// (see `BasicMailbox::is_busy`) instead of force-sending regardless.
+    /// Meant for best-effort notifications where piling another message
+    /// behind an already-backlogged mailbox does more harm than the
+    /// notification is worth. A mailbox flagged via
+    /// `BasicMailbox::set_recovery_target` is delivered to unconditionally,
+    /// since coordinated-recovery control messages need to reach it even
+    /// while it's busy.
+    pub fn broadcast_skip_busy(&self, mut msg_gen: impl FnMut() -> N::Message) {
+        let mailboxes = self.normals.lock().unwrap();
+        for mailbox in mailboxes.values() {
+            if mailbox.is_busy() && !mailbox.is_recovery_target() {
+                continue;
+            }
+            let _ = mailbox.force_send(msg_gen(), &self.normal_scheduler);
+        }
+    }
+
     /// Try to notify all fsm that the cluster is being shutdown.
     pub fn broadcast_shutdown(&self) {
         info!("broadcasting shutdown");
@@ -280,6 +323,40 @@ where
     pub fn clear_cache(&self) {
         unsafe { &mut *self.caches.as_ptr() }.clear();
     }
+
+    /// Returns the addresses of all registered mailboxes that have gone
+    /// without a message for at least `idle_after`.
+    ///
+    /// STATUS: infeasible as scoped -- see
+    /// [`BasicMailbox::idle_duration`](crate::mailbox::BasicMailbox::idle_duration)'s
+    /// doc comment. This only identifies quiescent mailboxes; nothing outside
+    /// its tests calls it, since `BasicMailbox` has no way to actually shrink a single
+    /// mailbox's channel in place. A real idle-close policy built on this
+    /// would need to `close` and later recreate the whole mailbox through
+    /// the router, not swap the channel underneath it.
+    pub fn idle_mailboxes(&self, idle_after: Duration) -> Vec<u64> {
+        let mailboxes = self.normals.lock().unwrap();
+        mailboxes
+            .iter()
+            .filter(|(_, mailbox)| mailbox.is_idle(idle_after))
+            .map(|(addr, _)| *addr)
+            .collect()
+    }
+
+    /// Returns the addresses of registered mailboxes that are currently
+    /// busy (see [`BasicMailbox::is_busy`]), for diagnosing write stalls.
+    /// Stops after collecting `max` addresses rather than scanning and
+    /// returning every busy mailbox, so a store with widespread backlog
+    /// doesn't turn a diagnostic call into an unbounded allocation.
+    pub fn busy_mailboxes(&self, max: usize) -> Vec<u64> {
+        let mailboxes = self.normals.lock().unwrap();
+        mailboxes
+            .iter()
+            .filter(|(_, mailbox)| mailbox.is_busy())
+            .map(|(addr, _)| *addr)
+            .take(max)
+            .collect()
+    }
 }
 
 impl<N: Fsm, C: Fsm, Ns: Clone, Cs: Clone> Clone for Router<N, C, Ns, Cs> {
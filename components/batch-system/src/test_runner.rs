@@ -71,17 +71,16 @@ pub struct Handler {
 
 impl Handler {
     fn handle(&mut self, r: &mut Runner) -> Option<usize> {
-        for _ in 0..16 {
-            match r.recv.try_recv() {
-                Ok(Message::Loop(count)) => {
+        for msg in r.recv.try_recv_batch(16) {
+            match msg {
+                Message::Loop(count) => {
                     // Some calculation to represent a CPU consuming work
                     for _ in 0..count {
                         r.res *= count;
                         r.res %= count + 1;
                     }
                 }
-                Ok(Message::Callback(cb)) => cb(r),
-                Err(_) => break,
+                Message::Callback(cb) => cb(r),
             }
         }
         Some(0)
@@ -4,10 +4,13 @@ use crate::metrics::*;
 use crate::IOBytes;
 use crate::IOType;
 
-use std::collections::VecDeque;
+use std::cell::UnsafeCell;
+#[cfg(feature = "region-io-stats")]
+use std::collections::HashMap;
+use std::collections::{HashSet, VecDeque};
 use std::ptr;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Mutex;
+use std::sync::{Mutex, MutexGuard};
 
 use bcc::{table::Table, Kprobe, BPF};
 use crossbeam_utils::CachePadded;
@@ -31,14 +34,34 @@ use crossbeam_utils::CachePadded;
 
 const MAX_THREAD_IDX: usize = 192;
 
-// Hold the BPF to keep it not dropped.
-// The two tables are `stats_by_type` and `type_by_pid` respectively.
-static mut BPF_CONTEXT: Option<BPFContext> = None;
-
 struct BPFContext {
     bpf: BPF,
     stats_table: Table,
     type_table: Table,
+    #[cfg(feature = "region-io-stats")]
+    region_table: Table,
+    #[cfg(feature = "region-io-stats")]
+    stats_region_table: Table,
+}
+
+// Safety: a `BPFContext` is only ever touched while holding `BPF_CONTEXT`'s
+// mutex, so it doesn't need to be `Sync` itself; sending it across the
+// thread that happens to take the lock is fine.
+unsafe impl Send for BPFContext {}
+
+lazy_static! {
+    // Hold the BPF to keep it not dropped.
+    // The two tables are `stats_by_type` and `type_by_pid` respectively.
+    //
+    // Wrapped in a `Mutex` instead of a raw `static mut` so init, flush and
+    // teardown can't race with each other without every call site having to
+    // reason about it in `unsafe` -- the borrow checker enforces exclusive
+    // access the same way it would for any other shared, mutable state.
+    static ref BPF_CONTEXT: Mutex<Option<BPFContext>> = Mutex::new(None);
+}
+
+fn bpf_context() -> MutexGuard<'static, Option<BPFContext>> {
+    BPF_CONTEXT.lock().unwrap()
 }
 
 // This array records the IO-type for every thread. The address of this array
@@ -53,23 +76,89 @@ struct BPFContext {
 // avoid false sharing.
 // Leave the last element as reserved, when there is no available index, all
 // other threads will be allocated to that index with IOType::Other always.
-static mut IO_TYPE_ARRAY: [CachePadded<IOType>; MAX_THREAD_IDX + 1] =
-    [CachePadded::new(IOType::Other); MAX_THREAD_IDX + 1];
+//
+// `IoTypeArray` wraps the raw per-slot storage so the "each thread only
+// touches its own slot" invariant above is encapsulated in one place
+// instead of every caller doing its own raw pointer indexing. The array
+// itself still has to be a plain `static` -- not, say, a `Vec` behind a
+// `Mutex` -- because eBPF needs a fixed, process-lifetime address to poke
+// at directly; that FFI requirement is exactly what a safe collection
+// can't give us, so a thin `unsafe impl Sync` here is the actual
+// irreducible unsafety, not a shortcut around it.
+struct IoTypeArray(UnsafeCell<[CachePadded<IOType>; MAX_THREAD_IDX + 1]>);
+
+unsafe impl Sync for IoTypeArray {}
+
+impl IoTypeArray {
+    fn get(&self, idx: usize) -> IOType {
+        unsafe { *(*self.0.get())[idx] }
+    }
+
+    fn set(&self, idx: usize, ty: IOType) {
+        unsafe { *(*self.0.get())[idx] = ty };
+    }
+
+    fn slot_ptr(&self, idx: usize) -> *const IOType {
+        unsafe { &(*self.0.get())[idx] as *const CachePadded<IOType> as *const IOType }
+    }
+}
+
+static IO_TYPE_ARRAY: IoTypeArray =
+    IoTypeArray(UnsafeCell::new([CachePadded::new(IOType::Other); MAX_THREAD_IDX + 1]));
+
+/// Same per-slot layout as `IoTypeArray`, but for the region id a thread was
+/// last tagged with via `set_io_type_and_region`. Only compiled under the
+/// `region-io-stats` feature, since it exists purely to feed the eBPF
+/// `region_by_pid` map that feature adds. `0` means "no region tagged" --
+/// region ids are never `0` in practice, so it doubles as the sentinel
+/// `get_io_stats_by_region` skips.
+#[cfg(feature = "region-io-stats")]
+struct IoRegionArray(UnsafeCell<[CachePadded<u64>; MAX_THREAD_IDX + 1]>);
+
+#[cfg(feature = "region-io-stats")]
+unsafe impl Sync for IoRegionArray {}
+
+#[cfg(feature = "region-io-stats")]
+impl IoRegionArray {
+    fn set(&self, idx: usize, region_id: u64) {
+        unsafe { *(*self.0.get())[idx] = region_id };
+    }
+
+    fn slot_ptr(&self, idx: usize) -> *const u64 {
+        unsafe { &(*self.0.get())[idx] as *const CachePadded<u64> as *const u64 }
+    }
+}
+
+#[cfg(feature = "region-io-stats")]
+static IO_REGION_ARRAY: IoRegionArray = IoRegionArray(UnsafeCell::new([CachePadded::new(0); MAX_THREAD_IDX + 1]));
 
 // The index of the element of IO_TYPE_ARRAY for this thread to access.
 thread_local! {
-    static IDX: IdxWrapper = unsafe {
+    static IDX: IdxWrapper = {
         let idx = IDX_ALLOCATOR.allocate();
-        if let Some(ctx) = BPF_CONTEXT.as_mut() {
+        if let Some(ctx) = bpf_context().as_mut() {
             let tid = nix::unistd::gettid().as_raw() as u32;
-            let ptr : *const *const _ = &IO_TYPE_ARRAY.as_ptr().add(idx.0);
-            ctx.type_table.set(
-                &mut tid.to_ne_bytes(),
-                std::slice::from_raw_parts_mut(
-                    ptr as *mut u8,
-                    std::mem::size_of::<*const IOType>(),
-                ),
-            ).unwrap();
+            unsafe {
+                let ptr: *const *const IOType = &IO_TYPE_ARRAY.slot_ptr(idx.0);
+                ctx.type_table.set(
+                    &mut tid.to_ne_bytes(),
+                    std::slice::from_raw_parts_mut(
+                        ptr as *mut u8,
+                        std::mem::size_of::<*const IOType>(),
+                    ),
+                ).unwrap();
+            }
+            #[cfg(feature = "region-io-stats")]
+            unsafe {
+                let ptr: *const *const u64 = &IO_REGION_ARRAY.slot_ptr(idx.0);
+                ctx.region_table.set(
+                    &mut tid.to_ne_bytes(),
+                    std::slice::from_raw_parts_mut(
+                        ptr as *mut u8,
+                        std::mem::size_of::<*const u64>(),
+                    ),
+                ).unwrap();
+            }
         }
         idx
     }
@@ -79,13 +168,15 @@ struct IdxWrapper(usize);
 
 impl Drop for IdxWrapper {
     fn drop(&mut self) {
-        unsafe { *IO_TYPE_ARRAY[self.0] = IOType::Other };
+        IO_TYPE_ARRAY.set(self.0, IOType::Other);
+        #[cfg(feature = "region-io-stats")]
+        IO_REGION_ARRAY.set(self.0, 0);
         IDX_ALLOCATOR.free(self.0);
 
         // drop() of static variables won't be called when program exits.
         // We need to call drop() of BPF to detach kprobe.
         if IDX_ALLOCATOR.is_all_free() {
-            unsafe { BPF_CONTEXT.take() };
+            bpf_context().take();
         }
     }
 }
@@ -128,26 +219,135 @@ impl IdxAllocator {
     fn is_all_free(&self) -> bool {
         self.count.load(Ordering::SeqCst) == 0
     }
+
+    /// Reserves a block of `n` indices in one `free_list` lock acquisition,
+    /// instead of `n` separate `allocate()` calls each contending on the
+    /// same mutex -- the pattern a thread pool starting all of its workers
+    /// at once would otherwise hit. Indices beyond what the free list can
+    /// satisfy fall back to the `MAX_THREAD_IDX` sentinel, same as
+    /// `allocate()` once the pool is exhausted.
+    fn reserve_block(&self, n: usize) -> Vec<usize> {
+        self.count.fetch_add(n, Ordering::SeqCst);
+        let mut free_list = self.free_list.lock().unwrap();
+        (0..n)
+            .map(|_| free_list.pop_front().unwrap_or(MAX_THREAD_IDX))
+            .collect()
+    }
+
+    /// Returns a block obtained from `reserve_block` to the front of the
+    /// free list, in its original order, in one lock acquisition, so the
+    /// next reservation hands the same indices out first. Mirrors `free`'s
+    /// sentinel handling: a `MAX_THREAD_IDX` entry (a reservation that
+    /// overflowed the pool) isn't a real index and is dropped rather than
+    /// requeued.
+    fn release_block(&self, indices: &[usize]) {
+        self.count.fetch_sub(indices.len(), Ordering::SeqCst);
+        let mut free_list = self.free_list.lock().unwrap();
+        for &idx in indices.iter().rev() {
+            if idx != MAX_THREAD_IDX {
+                free_list.push_front(idx);
+            }
+        }
+    }
+}
+
+/// Reserves a block of `n` thread indices up front, taken from the front
+/// of the free list and not necessarily contiguous, for a
+/// thread pool to hand its workers as they start instead of each worker
+/// separately contending on `IDX_ALLOCATOR`'s free-list mutex during its
+/// own lazy registration (via the `IDX` thread-local's first access).
+/// Indices returned here are plain integers, not tied to any thread yet --
+/// the caller is responsible for getting each one to exactly the worker
+/// meant to use it. Release a block with `release_indices` once its
+/// workers are done; the per-thread `allocate`/`free` path via `IDX` is
+/// unaffected and keeps working for any thread that isn't part of a pool.
+pub fn reserve_indices(n: usize) -> Vec<usize> {
+    IDX_ALLOCATOR.reserve_block(n)
+}
+
+/// Returns a block of indices obtained from `reserve_indices` to the front
+/// of the free list, so the next `reserve_indices`/`allocate` call recycles
+/// them first.
+pub fn release_indices(indices: &[usize]) {
+    IDX_ALLOCATOR.release_block(indices);
+    if IDX_ALLOCATOR.is_all_free() {
+        bpf_context().take();
+    }
 }
 
 pub fn set_io_type(new_io_type: IOType) {
-    unsafe {
-        IDX.with(|idx| {
-            // if MAX_THREAD_IDX, keep IOType::Other always
-            if idx.0 != MAX_THREAD_IDX {
-                *IO_TYPE_ARRAY[idx.0] = new_io_type;
-            }
-        })
-    };
+    IDX.with(|idx| {
+        // if MAX_THREAD_IDX, keep IOType::Other always
+        if idx.0 != MAX_THREAD_IDX {
+            IO_TYPE_ARRAY.set(idx.0, new_io_type);
+        }
+    });
 }
 
 pub fn get_io_type() -> IOType {
-    unsafe { *IDX.with(|idx| IO_TYPE_ARRAY[idx.0]) }
+    IDX.with(|idx| IO_TYPE_ARRAY.get(idx.0))
+}
+
+/// Same as `set_io_type`, but additionally tags the calling thread with
+/// `region_id` so its IO also gets counted in `get_io_stats_by_region`,
+/// alongside the plain per-type counting `set_io_type` always does. Keying
+/// stats by region as well as type is heavier -- `biosnoop.c` grows a
+/// second eBPF hash map and every IO completion pays one extra lookup to
+/// populate it -- so it only exists under the `region-io-stats` feature.
+#[cfg(feature = "region-io-stats")]
+pub fn set_io_type_and_region(new_io_type: IOType, region_id: u64) {
+    IDX.with(|idx| {
+        if idx.0 != MAX_THREAD_IDX {
+            IO_TYPE_ARRAY.set(idx.0, new_io_type);
+            IO_REGION_ARRAY.set(idx.0, region_id);
+        }
+    });
+}
+
+/// Forces the thread-local `IDX` to be allocated and registered into the
+/// BPF `type_by_pid` map up front, instead of lazily on the first
+/// `set_io_type()` call. Without this, the very first IO issued by a fresh
+/// thread can race with the lazy registration and be mis-attributed to
+/// `IOType::Other`. Intended to be called from a thread pool's
+/// `after_start` hook so every worker is registered before it handles work.
+pub fn register_current_thread() {
+    IDX.with(|_| {});
+}
+
+/// Key layout for the eBPF `stats_by_type_region` table, mirroring
+/// `region_key_t` in `biosnoop.c` byte for byte: `IOType`'s `#[repr(C)]`
+/// discriminant followed by the region id, in that field order.
+#[cfg(feature = "region-io-stats")]
+#[repr(C)]
+struct RegionStatsKey {
+    io_type: IOType,
+    region_id: u64,
+}
+
+/// Returns every `(IOType, region_id)` pair biosnoop has recorded stats for,
+/// as tagged by `set_io_type_and_region`. Only ever non-empty once the eBPF
+/// program has actually seen IO from a thread tagged with a region -- there
+/// is no separate "reset" from `set_io_type_and_region` back to plain
+/// per-type counting, since untagged threads (region id `0`) simply never
+/// appear here.
+#[cfg(feature = "region-io-stats")]
+pub fn get_io_stats_by_region() -> HashMap<(IOType, u64), IOBytes> {
+    let mut stats = HashMap::new();
+    if let Some(ctx) = bpf_context().as_mut() {
+        for e in ctx.stats_region_table.iter() {
+            unsafe {
+                let key = ptr::read_unaligned(e.key.as_ptr() as *const RegionStatsKey);
+                let value = ptr::read_unaligned(e.value.as_ptr() as *const IOBytes);
+                stats.insert((key.io_type, key.region_id), value);
+            }
+        }
+    }
+    stats
 }
 
 pub(crate) fn fetch_io_bytes(mut io_type: IOType) -> IOBytes {
-    unsafe {
-        if let Some(ctx) = BPF_CONTEXT.as_mut() {
+    if let Some(ctx) = bpf_context().as_mut() {
+        unsafe {
             let io_type_buf_ptr = &mut io_type as *mut IOType as *mut u8;
             let mut io_type_buf =
                 std::slice::from_raw_parts_mut(io_type_buf_ptr, std::mem::size_of::<IOType>());
@@ -160,14 +360,104 @@ pub(crate) fn fetch_io_bytes(mut io_type: IOType) -> IOBytes {
     IOBytes::default()
 }
 
+// Some kernels renamed these symbols (e.g. `blk_account_io_completion` became
+// `blk_account_io_done`), so a `Kprobe` attached to only the name a given
+// TiKV release happened to be built against fails with a cryptic bcc error
+// and IO tracking silently never starts. List candidates newest-first so a
+// kernel that has both (unlikely, but cheap to allow) prefers the current
+// name.
+const IO_START_SYMBOL_CANDIDATES: &[&str] = &["blk_account_io_start"];
+const IO_COMPLETION_SYMBOL_CANDIDATES: &[&str] =
+    &["blk_account_io_done", "blk_account_io_completion"];
+
+/// The kernel symbols visible to kprobes on this machine, read from
+/// `/proc/kallsyms`. Returns an empty set (rather than erroring) if the file
+/// can't be read, so callers see "nothing available" and report a clear
+/// error instead of panicking on a permissions issue.
+fn kallsyms_symbols() -> HashSet<String> {
+    std::fs::read_to_string("/proc/kallsyms")
+        .map(|contents| {
+            contents
+                .lines()
+                .filter_map(|line| line.split_whitespace().nth(2))
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Picks the first of `candidates` that's present in `available`, in order.
+/// Kept separate from `kallsyms_symbols` so the fallback-selection logic
+/// itself can be unit tested against a mocked availability set instead of
+/// depending on the running kernel's actual symbol table.
+fn select_available_symbol<'a>(candidates: &[&'a str], available: &HashSet<String>) -> Option<&'a str> {
+    candidates.iter().copied().find(|c| available.contains(*c))
+}
+
+// The `region-io-stats` feature's eBPF additions to `biosnoop.c`, spliced in
+// via the same textual `##PLACEHOLDER##` substitution `init_io_snooper`
+// already uses for `##TGID##`. Kept out of the `.c` file entirely when the
+// feature is off, rather than compiled-in-but-unused, so the extra hash map
+// and extra per-completion lookup this feature costs are only ever paid for
+// when a build actually opts into per-region attribution.
+#[cfg(feature = "region-io-stats")]
+const REGION_STATS_DECLS: &str = r#"
+struct region_key_t {
+  io_type type;
+  u64 region_id;
+};
+
+BPF_HASH(region_by_pid, u32, u64 *);
+BPF_HASH(stats_by_type_region, struct region_key_t, struct stats_t);
+"#;
+
+#[cfg(feature = "region-io-stats")]
+const REGION_STATS_LOOKUP: &str = r#"
+  u64 **region_ptr = region_by_pid.lookup(&pid);
+  if (region_ptr == 0) {
+    info.region_id = 0;
+  } else {
+    int err = bpf_probe_read(&info.region_id, sizeof(u64), (void *)*region_ptr);
+    if (err != 0) {
+      info.region_id = 0;
+    }
+  }
+"#;
+
+#[cfg(feature = "region-io-stats")]
+const REGION_STATS_UPDATE: &str = r#"
+  if (info->region_id != 0) {
+    struct region_key_t region_key = {.type = type, .region_id = info->region_id};
+    struct stats_t region_zero = {}, *region_val;
+    region_val = stats_by_type_region.lookup_or_init(&region_key, &region_zero);
+    if (rwflag == 1) {
+      __sync_fetch_and_add(&region_val->write, req->__data_len);
+    } else {
+      __sync_fetch_and_add(&region_val->read, req->__data_len);
+    }
+  }
+"#;
+
 pub fn init_io_snooper() -> Result<(), String> {
-    unsafe {
-        if BPF_CONTEXT.is_some() {
-            return Ok(());
-        }
+    // Hold the lock across the whole build, not just the initial check, so
+    // concurrent callers can't both observe "not initialized" and race to
+    // attach the kprobes twice.
+    let mut ctx = bpf_context();
+    if ctx.is_some() {
+        return Ok(());
     }
 
     let code = include_str!("biosnoop.c").replace("##TGID##", &nix::unistd::getpid().to_string());
+    #[cfg(feature = "region-io-stats")]
+    let code = code
+        .replace("// ##REGION_STATS_DECLS##", REGION_STATS_DECLS)
+        .replace("// ##REGION_STATS_LOOKUP##", REGION_STATS_LOOKUP)
+        .replace("// ##REGION_STATS_UPDATE##", REGION_STATS_UPDATE);
+    #[cfg(not(feature = "region-io-stats"))]
+    let code = code
+        .replace("// ##REGION_STATS_DECLS##", "")
+        .replace("// ##REGION_STATS_LOOKUP##", "")
+        .replace("// ##REGION_STATS_UPDATE##", "");
 
     // TODO: When using bpf_get_ns_current_pid_tgid of newer kernel, need
     // to get the device id and inode number.
@@ -188,26 +478,49 @@ pub fn init_io_snooper() -> Result<(), String> {
 
     // compile the above BPF code!
     let mut bpf = BPF::new(&code).map_err(|e| e.to_string())?;
+
+    let available = kallsyms_symbols();
+    let start_symbol = select_available_symbol(IO_START_SYMBOL_CANDIDATES, &available)
+        .ok_or_else(|| {
+            format!(
+                "no kprobe-attachable symbol found for the IO-start hook; tried: {}",
+                IO_START_SYMBOL_CANDIDATES.join(", ")
+            )
+        })?;
+    let completion_symbol = select_available_symbol(IO_COMPLETION_SYMBOL_CANDIDATES, &available)
+        .ok_or_else(|| {
+            format!(
+                "no kprobe-attachable symbol found for the IO-completion hook; tried: {}",
+                IO_COMPLETION_SYMBOL_CANDIDATES.join(", ")
+            )
+        })?;
+
     // attach kprobes
     Kprobe::new()
         .handler("trace_req_start")
-        .function("blk_account_io_start")
+        .function(start_symbol)
         .attach(&mut bpf)
         .map_err(|e| e.to_string())?;
     Kprobe::new()
         .handler("trace_req_completion")
-        .function("blk_account_io_completion")
+        .function(completion_symbol)
         .attach(&mut bpf)
         .map_err(|e| e.to_string())?;
     let stats_table = bpf.table("stats_by_type").map_err(|e| e.to_string())?;
     let type_table = bpf.table("type_by_pid").map_err(|e| e.to_string())?;
-    unsafe {
-        BPF_CONTEXT = Some(BPFContext {
-            bpf,
-            stats_table,
-            type_table,
-        });
-    }
+    #[cfg(feature = "region-io-stats")]
+    let region_table = bpf.table("region_by_pid").map_err(|e| e.to_string())?;
+    #[cfg(feature = "region-io-stats")]
+    let stats_region_table = bpf.table("stats_by_type_region").map_err(|e| e.to_string())?;
+    *ctx = Some(BPFContext {
+        bpf,
+        stats_table,
+        type_table,
+        #[cfg(feature = "region-io-stats")]
+        region_table,
+        #[cfg(feature = "region-io-stats")]
+        stats_region_table,
+    });
     Ok(())
 }
 
@@ -244,8 +557,8 @@ macro_rules! flush_io_latency {
 }
 
 pub(crate) fn flush_io_latency_metrics() {
-    unsafe {
-        if let Some(ctx) = BPF_CONTEXT.as_mut() {
+    if let Some(ctx) = bpf_context().as_mut() {
+        unsafe {
             flush_io_latency!(ctx.bpf, other);
             flush_io_latency!(ctx.bpf, foreground_read);
             flush_io_latency!(ctx.bpf, foreground_write);
@@ -256,17 +569,21 @@ pub(crate) fn flush_io_latency_metrics() {
             flush_io_latency!(ctx.bpf, gc);
             flush_io_latency!(ctx.bpf, import);
             flush_io_latency!(ctx.bpf, export);
+            flush_io_latency!(ctx.bpf, backup);
+            flush_io_latency!(ctx.bpf, restore);
+            flush_io_latency!(ctx.bpf, raft_log);
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{fetch_io_bytes, flush_io_latency_metrics};
+    use super::{fetch_io_bytes, flush_io_latency_metrics, select_available_symbol};
     use crate::iosnoop::imp::{BPF_CONTEXT, MAX_THREAD_IDX};
     use crate::metrics::*;
-    use crate::{get_io_type, init_io_snooper, set_io_type, IOType};
+    use crate::{get_io_type, init_io_snooper, register_current_thread, set_io_type, IOType};
     use rand::Rng;
+    use std::collections::HashSet;
     use std::sync::{Arc, Condvar, Mutex};
     use std::{
         fs::OpenOptions, io::Read, io::Seek, io::SeekFrom, io::Write, os::unix::fs::OpenOptionsExt,
@@ -278,16 +595,166 @@ mod tests {
     use maligned::A512;
     use maligned::{AsBytes, AsBytesMut};
 
+    #[test]
+    fn test_select_available_symbol_prefers_earlier_candidates() {
+        let mut available = HashSet::new();
+        available.insert("blk_account_io_completion".to_string());
+        available.insert("blk_account_io_done".to_string());
+
+        // Both candidates are present: the earlier (preferred) one wins.
+        assert_eq!(
+            select_available_symbol(
+                &["blk_account_io_done", "blk_account_io_completion"],
+                &available
+            ),
+            Some("blk_account_io_done")
+        );
+    }
+
+    #[test]
+    fn test_select_available_symbol_falls_back_to_older_name() {
+        let mut available = HashSet::new();
+        available.insert("blk_account_io_completion".to_string());
+
+        // Only the older name is present on this (mocked) kernel.
+        assert_eq!(
+            select_available_symbol(
+                &["blk_account_io_done", "blk_account_io_completion"],
+                &available
+            ),
+            Some("blk_account_io_completion")
+        );
+    }
+
+    #[test]
+    fn test_select_available_symbol_none_when_nothing_matches() {
+        let available = HashSet::new();
+        assert_eq!(
+            select_available_symbol(&["blk_account_io_done", "blk_account_io_completion"], &available),
+            None
+        );
+    }
+
     #[test]
     fn test_biosnoop() {
         init_io_snooper().unwrap();
         // Test cases are running in parallel, while they depend on the same global variables.
         // To make them not affect each other, run them in sequence.
         test_thread_idx_allocation();
+        test_reserve_and_release_indices();
         test_io_context();
-        unsafe {
-            BPF_CONTEXT.take();
+        test_raft_log_io_type();
+        test_register_current_thread();
+        test_concurrent_init();
+        test_sequential_double_init_keeps_tracking_working();
+        #[cfg(feature = "region-io-stats")]
+        test_region_io_stats();
+        BPF_CONTEXT.lock().unwrap().take();
+    }
+
+    // `init_io_snooper` is idempotent -- see the "already initialized"
+    // check and the comment on why the lock is held across the whole build,
+    // both above -- but that only proves a second call doesn't panic or
+    // deadlock. This additionally proves the *existing* attachment it kept
+    // is still the one actually wired up to the kernel, by calling it again
+    // mid-test and checking IO is still attributed correctly afterwards.
+    fn test_sequential_double_init_keeps_tracking_working() {
+        set_io_type(IOType::Compaction);
+        let tmp = TempDir::new().unwrap();
+        let file_path = tmp
+            .path()
+            .join("test_sequential_double_init_keeps_tracking_working");
+        let mut f = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .custom_flags(O_DIRECT)
+            .open(&file_path)
+            .unwrap();
+        let mut w = vec![A512::default(); 2];
+        w.as_bytes_mut()[512] = 42;
+
+        // Re-initializing must not overwrite `BPF_CONTEXT` with a fresh,
+        // unrelated attachment out from under IO that's about to run.
+        init_io_snooper().unwrap();
+
+        let compaction_bytes_before = fetch_io_bytes(IOType::Compaction);
+        f.write(w.as_bytes()).unwrap();
+        f.sync_all().unwrap();
+        let compaction_bytes = fetch_io_bytes(IOType::Compaction);
+        assert_ne!((compaction_bytes - compaction_bytes_before).write, 0);
+        set_io_type(IOType::Other);
+    }
+
+    #[cfg(feature = "region-io-stats")]
+    fn test_region_io_stats() {
+        use crate::iosnoop::imp::{get_io_stats_by_region, set_io_type_and_region};
+
+        let region_id = 42;
+        set_io_type_and_region(IOType::Compaction, region_id);
+        let tmp = TempDir::new().unwrap();
+        let file_path = tmp.path().join("test_region_io_stats");
+        let mut f = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .custom_flags(O_DIRECT)
+            .open(&file_path)
+            .unwrap();
+        let mut w = vec![A512::default(); 2];
+        w.as_bytes_mut()[512] = 42;
+        f.write(w.as_bytes()).unwrap();
+        f.sync_all().unwrap();
+        drop(f);
+
+        let stats = get_io_stats_by_region();
+        let (_, region_bytes) = stats
+            .iter()
+            .find(|(&(ty, id), _)| ty == IOType::Compaction && id == region_id)
+            .expect("no stats recorded for the tagged region");
+        assert_ne!(region_bytes.write, 0);
+
+        set_io_type(IOType::Other);
+    }
+
+    fn test_concurrent_init() {
+        // Several threads racing to initialize should all observe success
+        // and leave exactly one live `BPFContext` behind, instead of one
+        // thread's init clobbering another's (e.g. double-attaching the
+        // kprobes or leaking the first attachment).
+        let handles: Vec<_> = (0..8)
+            .map(|_| std::thread::spawn(init_io_snooper))
+            .collect();
+        for h in handles {
+            h.join().unwrap().unwrap();
         }
+        assert!(BPF_CONTEXT.lock().unwrap().is_some());
+    }
+
+    fn test_register_current_thread() {
+        // Registration happens on a fresh thread, before any `set_io_type()`
+        // call has a chance to allocate the thread-local index lazily. The
+        // very first IO on that thread should still be attributed correctly.
+        std::thread::spawn(|| {
+            register_current_thread();
+            set_io_type(IOType::Compaction);
+
+            let tmp = TempDir::new().unwrap();
+            let file_path = tmp.path().join("test_register_current_thread");
+            let mut f = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .custom_flags(O_DIRECT)
+                .open(&file_path)
+                .unwrap();
+            let mut w = vec![A512::default(); 2];
+            w.as_bytes_mut()[512] = 42;
+            let compaction_bytes_before = fetch_io_bytes(IOType::Compaction);
+            f.write(w.as_bytes()).unwrap();
+            f.sync_all().unwrap();
+            let compaction_bytes = fetch_io_bytes(IOType::Compaction);
+            assert_ne!((compaction_bytes - compaction_bytes_before).write, 0);
+        })
+        .join()
+        .unwrap();
     }
 
     fn test_io_context() {
@@ -339,6 +806,48 @@ mod tests {
         assert_ne!(IO_LATENCY_MICROS_VEC.other.read.get_sample_count(), 0);
     }
 
+    // Asserts that IO tagged `IOType::RaftLog` -- the type raft log catch-up
+    // reads are tagged with, see `PeerStorage::entries` -- is attributed to
+    // its own bucket instead of bleeding into whatever type happened to be
+    // set beforehand.
+    fn test_raft_log_io_type() {
+        set_io_type(IOType::Other);
+        let tmp = TempDir::new().unwrap();
+        let file_path = tmp.path().join("test_raft_log_io_type");
+        let mut f = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .custom_flags(O_DIRECT)
+            .open(&file_path)
+            .unwrap();
+        let mut w = vec![A512::default(); 2];
+        w.as_bytes_mut()[512] = 42;
+        f.write(w.as_bytes()).unwrap();
+        f.sync_all().unwrap();
+        drop(f);
+
+        let raft_log_bytes_before = fetch_io_bytes(IOType::RaftLog);
+        let other_bytes_before = fetch_io_bytes(IOType::Other);
+        set_io_type(IOType::RaftLog);
+        let mut f = OpenOptions::new()
+            .read(true)
+            .custom_flags(O_DIRECT)
+            .open(&file_path)
+            .unwrap();
+        let mut r = vec![A512::default(); 2];
+        assert_ne!(f.read(&mut r.as_bytes_mut()).unwrap(), 0);
+        drop(f);
+        set_io_type(IOType::Other);
+
+        let raft_log_bytes = fetch_io_bytes(IOType::RaftLog);
+        let other_bytes = fetch_io_bytes(IOType::Other);
+        assert_ne!((raft_log_bytes - raft_log_bytes_before).read, 0);
+        assert_eq!((other_bytes - other_bytes_before).read, 0);
+
+        flush_io_latency_metrics();
+        assert_ne!(IO_LATENCY_MICROS_VEC.raft_log.read.get_sample_count(), 0);
+    }
+
     fn test_thread_idx_allocation() {
         // the thread indexes should be recycled.
         for _ in 1..=MAX_THREAD_IDX * 2 {
@@ -397,6 +906,42 @@ mod tests {
         }
     }
 
+    fn test_reserve_and_release_indices() {
+        // Reserve a block up front, confirm it holds no duplicate index,
+        // and hand one index to each of several worker threads.
+        let reserved = super::reserve_indices(4);
+        assert_eq!(reserved.len(), 4);
+        let mut seen = HashSet::new();
+        for &idx in &reserved {
+            assert!(seen.insert(idx), "duplicate reserved index {}", idx);
+        }
+
+        let handles: Vec<_> = reserved
+            .iter()
+            .map(|&idx| {
+                std::thread::spawn(move || {
+                    // Each worker just confirms its assigned index is a
+                    // real slot, not the overflow sentinel.
+                    assert_ne!(idx, MAX_THREAD_IDX);
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        super::release_indices(&reserved);
+
+        // Released indices are recycled: reserving the same count again
+        // draws from the same recently-freed pool rather than growing it.
+        let reserved_again = super::reserve_indices(4);
+        assert_eq!(reserved_again.len(), 4);
+        for idx in reserved_again.iter() {
+            assert!(reserved.contains(idx));
+        }
+        super::release_indices(&reserved_again);
+    }
+
     #[bench]
     #[ignore]
     fn bench_write_enable_io_snoop(b: &mut Bencher) {
@@ -407,7 +952,7 @@ mod tests {
     #[bench]
     #[ignore]
     fn bench_write_disable_io_snoop(b: &mut Bencher) {
-        unsafe { BPF_CONTEXT = None };
+        BPF_CONTEXT.lock().unwrap().take();
         bench_write(b);
     }
 
@@ -421,7 +966,7 @@ mod tests {
     #[bench]
     #[ignore]
     fn bench_read_disable_io_snoop(b: &mut Bencher) {
-        unsafe { BPF_CONTEXT = None };
+        BPF_CONTEXT.lock().unwrap().take();
         bench_read(b);
     }
 
@@ -9,4 +9,343 @@ mod imp;
 mod imp;
 
 pub(crate) use imp::{fetch_io_bytes, flush_io_latency_metrics};
-pub use imp::{get_io_type, init_io_snooper, set_io_type};
+pub use imp::{get_io_type, init_io_snooper, register_current_thread};
+#[cfg(feature = "region-io-stats")]
+pub use imp::{get_io_stats_by_region, set_io_type_and_region};
+
+use strum::{EnumCount, IntoEnumIterator};
+
+use crate::{IOBytes, IOType};
+
+/// Maximum number of `(IOType, Instant)` transitions `set_io_type` retains
+/// per thread under the `io-type-history` feature; see
+/// `get_io_type_history`.
+#[cfg(feature = "io-type-history")]
+const IO_TYPE_HISTORY_CAPACITY: usize = 32;
+
+#[cfg(feature = "io-type-history")]
+thread_local! {
+    static IO_TYPE_HISTORY: std::cell::RefCell<std::collections::VecDeque<(IOType, std::time::Instant)>> =
+        std::cell::RefCell::new(std::collections::VecDeque::with_capacity(IO_TYPE_HISTORY_CAPACITY));
+}
+
+/// Sets the calling thread's current IO type. Under the `io-type-history`
+/// feature, also appends the transition to a small per-thread ring buffer
+/// queryable via `get_io_type_history` -- chasing an IO-attribution bug
+/// otherwise means guessing what a thread was tagged as at the moment of a
+/// suspicious read/write, with no trail to check. Off by default, in which
+/// case this is exactly the thread-local store it always was.
+pub fn set_io_type(new_io_type: IOType) {
+    #[cfg(feature = "io-type-history")]
+    IO_TYPE_HISTORY.with(|history| {
+        let mut history = history.borrow_mut();
+        if history.len() == IO_TYPE_HISTORY_CAPACITY {
+            history.pop_front();
+        }
+        history.push_back((new_io_type, std::time::Instant::now()));
+    });
+    imp::set_io_type(new_io_type);
+}
+
+/// Returns the calling thread's recorded `(IOType, Instant)` transitions,
+/// oldest first, as set by `set_io_type`. Only available under the
+/// `io-type-history` feature.
+#[cfg(feature = "io-type-history")]
+pub fn get_io_type_history() -> Vec<(IOType, std::time::Instant)> {
+    IO_TYPE_HISTORY.with(|history| history.borrow().iter().cloned().collect())
+}
+
+// A single-interval delta larger than this is treated as implausible rather
+// than trusted, since it usually means either a long gap between calls let
+// counters wrap, or the underlying stats table was reset out from under us.
+const DEFAULT_MAX_DELTA_BYTES: u64 = 1 << 40; // 1TiB
+
+/// Tracks the IO bytes attributed to one [`IOType`] and reports only the
+/// delta since the last call, refreshing its baseline every time.
+pub struct IOContext {
+    io_type: IOType,
+    last: IOBytes,
+    max_delta_bytes: u64,
+}
+
+impl IOContext {
+    pub fn new(io_type: IOType) -> Self {
+        IOContext {
+            io_type,
+            last: fetch_io_bytes(io_type),
+            max_delta_bytes: DEFAULT_MAX_DELTA_BYTES,
+        }
+    }
+
+    /// Sets the ceiling above which a single-interval delta is considered
+    /// implausible. Exposed so callers with a known polling interval and
+    /// expected throughput can tighten it.
+    pub fn set_max_delta_bytes(&mut self, max_delta_bytes: u64) {
+        self.max_delta_bytes = max_delta_bytes;
+    }
+
+    /// Returns the change in IO bytes since the last call, then re-baselines
+    /// against the current reading. A delta above the configured ceiling is
+    /// reported as zero instead of the implausible value, so a single
+    /// spurious huge value can't pollute a rate graph.
+    pub fn delta_and_refresh(&mut self) -> IOBytes {
+        self.refresh_from(fetch_io_bytes(self.io_type))
+    }
+
+    /// Captures this context's current IO bytes as an opaque baseline, for
+    /// later use with `delta_since`. Unlike `delta_and_refresh`, taking a
+    /// mark doesn't touch `self.last`, so it doesn't disturb the context's
+    /// own rolling baseline and multiple marks can be measured against
+    /// concurrently -- e.g. one mark per in-flight backup checkpoint.
+    pub fn mark(&self) -> IoMark {
+        self.mark_from(fetch_io_bytes(self.io_type))
+    }
+
+    /// Returns the change in IO bytes between `mark` and now, subject to the
+    /// same implausible-jump ceiling as `delta_and_refresh`. `IOContext`
+    /// only ever tracks the single `IOType` it was constructed with, so this
+    /// returns a plain `IOBytes` rather than a per-`IOType` breakdown.
+    pub fn delta_since(&self, mark: IoMark) -> IOBytes {
+        self.delta_from(mark, fetch_io_bytes(self.io_type))
+    }
+
+    fn mark_from(&self, current: IOBytes) -> IoMark {
+        IoMark(current)
+    }
+
+    fn delta_from(&self, mark: IoMark, current: IOBytes) -> IOBytes {
+        self.clamp(current - mark.0)
+    }
+
+    fn refresh_from(&mut self, current: IOBytes) -> IOBytes {
+        let delta = self.clamp(current - self.last);
+        self.last = current;
+        delta
+    }
+
+    fn clamp(&self, delta: IOBytes) -> IOBytes {
+        if delta.read > self.max_delta_bytes || delta.write > self.max_delta_bytes {
+            return IOBytes::default();
+        }
+        delta
+    }
+}
+
+/// An opaque baseline captured by `IOContext::mark`, redeemed by
+/// `IOContext::delta_since` to measure the IO issued in between.
+#[derive(Debug, Copy, Clone)]
+pub struct IoMark(IOBytes);
+
+/// Snapshots baseline IO bytes for every [`IOType`] and reports which ones
+/// saw no read or write activity since that baseline. Meant for a startup
+/// (or periodic) self-check that warns when a subsystem expected to tag its
+/// IO with a dedicated type (e.g. `Compaction`) never actually called
+/// `set_io_type` for it -- a bug that otherwise shows up only as `Other`
+/// quietly running higher than expected, with no dedicated signal of its
+/// own.
+pub struct IOTypeActivityMonitor {
+    baseline: [IOBytes; IOType::COUNT],
+}
+
+impl IOTypeActivityMonitor {
+    pub fn new() -> Self {
+        let mut baseline = [IOBytes::default(); IOType::COUNT];
+        for io_type in IOType::iter() {
+            baseline[io_type as usize] = fetch_io_bytes(io_type);
+        }
+        IOTypeActivityMonitor { baseline }
+    }
+
+    /// Returns every `IOType` with no recorded bytes since this monitor's
+    /// baseline, then re-baselines against the current reading so a
+    /// subsequent call only reports types quiet over the new interval.
+    pub fn zero_activity_types(&mut self) -> Vec<IOType> {
+        let mut zero = Vec::new();
+        self.zero_activity_types_into(&mut zero);
+        zero
+    }
+
+    /// Same as `zero_activity_types`, but appends into a caller-provided,
+    /// reusable buffer instead of allocating a fresh `Vec` every call --
+    /// worthwhile on a metrics loop that polls this monitor frequently. The
+    /// buffer is cleared before being refilled.
+    pub fn zero_activity_types_into(&mut self, buf: &mut Vec<IOType>) {
+        self.refresh_and_report(fetch_io_bytes, buf)
+    }
+
+    fn refresh_and_report(
+        &mut self,
+        mut current_bytes: impl FnMut(IOType) -> IOBytes,
+        buf: &mut Vec<IOType>,
+    ) {
+        buf.clear();
+        for io_type in IOType::iter() {
+            let current = current_bytes(io_type);
+            let delta = current - self.baseline[io_type as usize];
+            if delta.read == 0 && delta.write == 0 {
+                buf.push(io_type);
+            }
+            self.baseline[io_type as usize] = current;
+        }
+    }
+}
+
+impl Default for IOTypeActivityMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delta_and_refresh_ceiling() {
+        let mut ctx = IOContext::new(IOType::Other);
+        ctx.set_max_delta_bytes(1024);
+        ctx.last = IOBytes { read: 0, write: 0 };
+
+        // A plausible delta passes through unchanged, and re-baselines.
+        let delta = ctx.refresh_from(IOBytes {
+            read: 512,
+            write: 0,
+        });
+        assert_eq!(delta.read, 512);
+
+        // An implausibly large jump -- e.g. from a long gap between calls or
+        // a reset stats table -- is reported as zero instead of propagated.
+        let delta = ctx.refresh_from(IOBytes {
+            read: u64::MAX,
+            write: 0,
+        });
+        assert_eq!(delta.read, 0);
+        assert_eq!(delta.write, 0);
+
+        // The baseline still moved, so the next call sees a normal delta.
+        let delta = ctx.refresh_from(IOBytes {
+            read: u64::MAX,
+            write: 100,
+        });
+        assert_eq!(delta.read, 0);
+        assert_eq!(delta.write, 100);
+    }
+
+    #[test]
+    fn test_mark_and_delta_since_measures_independent_window() {
+        let ctx = IOContext::new(IOType::Other);
+
+        // Two overlapping windows, opened at different baselines.
+        let mark1 = ctx.mark_from(IOBytes {
+            read: 100,
+            write: 0,
+        });
+        let mark2 = ctx.mark_from(IOBytes {
+            read: 350,
+            write: 20,
+        });
+
+        let current = IOBytes {
+            read: 500,
+            write: 20,
+        };
+        assert_eq!(ctx.delta_from(mark1, current).read, 400);
+        assert_eq!(ctx.delta_from(mark2, current).read, 150);
+        assert_eq!(ctx.delta_from(mark2, current).write, 0);
+
+        // An implausible jump since the mark is still clamped to zero.
+        let huge = ctx.delta_from(
+            mark1,
+            IOBytes {
+                read: u64::MAX,
+                write: 0,
+            },
+        );
+        assert_eq!(huge.read, 0);
+        assert_eq!(huge.write, 0);
+    }
+
+    #[test]
+    #[cfg(feature = "io-type-history")]
+    fn test_set_io_type_records_history_in_order_and_evicts_oldest() {
+        IO_TYPE_HISTORY.with(|history| history.borrow_mut().clear());
+
+        set_io_type(IOType::ForegroundWrite);
+        set_io_type(IOType::Compaction);
+        set_io_type(IOType::Gc);
+
+        let history = get_io_type_history();
+        let types: Vec<IOType> = history.iter().map(|(t, _)| *t).collect();
+        assert_eq!(
+            types,
+            vec![IOType::ForegroundWrite, IOType::Compaction, IOType::Gc]
+        );
+
+        for _ in 0..IO_TYPE_HISTORY_CAPACITY {
+            set_io_type(IOType::Other);
+        }
+        let history = get_io_type_history();
+        assert_eq!(history.len(), IO_TYPE_HISTORY_CAPACITY);
+        assert!(history.iter().all(|(t, _)| *t == IOType::Other));
+    }
+
+    #[test]
+    fn test_zero_activity_types_reports_untouched_types() {
+        let mut monitor = IOTypeActivityMonitor {
+            baseline: [IOBytes::default(); IOType::COUNT],
+        };
+        let mut counters = [IOBytes::default(); IOType::COUNT];
+        counters[IOType::ForegroundWrite as usize] = IOBytes {
+            read: 0,
+            write: 4096,
+        };
+        counters[IOType::Compaction as usize] = IOBytes {
+            read: 2048,
+            write: 0,
+        };
+
+        let mut zero = Vec::new();
+        monitor.refresh_and_report(|io_type| counters[io_type as usize], &mut zero);
+        assert!(!zero.contains(&IOType::ForegroundWrite));
+        assert!(!zero.contains(&IOType::Compaction));
+        assert!(zero.contains(&IOType::Other));
+        assert!(zero.contains(&IOType::Gc));
+        assert_eq!(zero.len(), IOType::COUNT - 2);
+
+        // The baseline moved forward, so a second call against the same
+        // unchanged counters reports every type -- including the two that
+        // were active a moment ago -- as quiet over this new interval.
+        let mut zero_again = Vec::new();
+        monitor.refresh_and_report(|io_type| counters[io_type as usize], &mut zero_again);
+        assert_eq!(zero_again.len(), IOType::COUNT);
+    }
+
+    #[test]
+    fn test_zero_activity_types_into_matches_allocating_path() {
+        let counters_v1 = {
+            let mut counters = [IOBytes::default(); IOType::COUNT];
+            counters[IOType::ForegroundWrite as usize] = IOBytes {
+                read: 0,
+                write: 4096,
+            };
+            counters
+        };
+        // Second iteration: counters unchanged, so every type reads quiet
+        // over the new interval.
+        let counters_v2 = counters_v1;
+
+        let mut allocating = IOTypeActivityMonitor::default();
+        let mut reused = IOTypeActivityMonitor::default();
+        let mut buf = Vec::new();
+
+        for counters in [counters_v1, counters_v2] {
+            let via_alloc = {
+                let mut zero = Vec::new();
+                allocating.refresh_and_report(|io_type| counters[io_type as usize], &mut zero);
+                zero
+            };
+            reused.refresh_and_report(|io_type| counters[io_type as usize], &mut buf);
+            assert_eq!(*buf, via_alloc);
+        }
+    }
+}
@@ -22,8 +22,22 @@ pub fn get_io_type() -> IOType {
     IO_TYPE.with(|io_type| io_type.get())
 }
 
+pub fn register_current_thread() {
+    IO_TYPE.with(|_| {});
+}
+
 pub(crate) fn flush_io_latency_metrics() {}
 
 pub(crate) fn fetch_io_bytes(_io_type: IOType) -> IOBytes {
     IOBytes::default()
 }
+
+#[cfg(feature = "region-io-stats")]
+pub fn set_io_type_and_region(new_io_type: IOType, _region_id: u64) {
+    set_io_type(new_io_type);
+}
+
+#[cfg(feature = "region-io-stats")]
+pub fn get_io_stats_by_region() -> std::collections::HashMap<(IOType, u64), IOBytes> {
+    std::collections::HashMap::new()
+}
@@ -15,10 +15,15 @@ mod metrics_manager;
 mod rate_limiter;
 
 pub use file::{File, OpenOptions};
-pub use iosnoop::{get_io_type, init_io_snooper, set_io_type};
+pub use iosnoop::{
+    get_io_type, init_io_snooper, register_current_thread, set_io_type, IOContext, IoMark,
+    IOTypeActivityMonitor,
+};
+#[cfg(feature = "io-type-history")]
+pub use iosnoop::get_io_type_history;
 pub use metrics_manager::{BytesFetcher, MetricsManager};
 pub use rate_limiter::{
-    get_io_rate_limiter, set_io_rate_limiter, IORateLimiter, IORateLimiterStatistics,
+    get_io_rate_limiter, set_io_rate_limiter, IORateLimiter, IORateLimiterStatistics, IoStatEntry,
     WithIORateLimit,
 };
 
@@ -34,7 +39,8 @@ use std::sync::{Arc, Mutex};
 
 use openssl::error::ErrorStack;
 use openssl::hash::{self, Hasher, MessageDigest};
-use strum::EnumCount;
+use serde::Serialize;
+use strum::{EnumCount, EnumIter};
 
 #[derive(Debug)]
 pub enum IOOp {
@@ -43,7 +49,7 @@ pub enum IOOp {
 }
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, EnumCount)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, EnumCount, EnumIter, Serialize)]
 pub enum IOType {
     Other,
     // Including coprocessor and storage read.
@@ -59,6 +65,17 @@ pub enum IOType {
     Gc,
     Import,
     Export,
+    // Added after `Export` rather than alongside it so every existing
+    // variant keeps its numeric discriminant -- `IOType` is read directly
+    // by the eBPF probe in `iosnoop/biosnoop.c`, which has its own copy of
+    // this enum that has to stay numerically in sync.
+    Backup,
+    Restore,
+    // Raft log catch-up reads issued by `PeerStorage::entries`, so recovery
+    // read pressure is isolated from `ForegroundRead`/`ForegroundWrite`
+    // instead of being folded into whichever type happened to be set on the
+    // raftstore thread beforehand.
+    RaftLog,
 }
 
 pub struct WithIOType {
@@ -79,6 +96,18 @@ impl Drop for WithIOType {
     }
 }
 
+/// Resets the current thread's IO type to `IOType::Other`.
+///
+/// Long-lived worker threads (e.g. yatp pool workers) can be reused across
+/// subsystems that each tag their own IO with `set_io_type`; whichever type
+/// was set last otherwise sticks and misattributes IO issued by whatever
+/// runs next. Call this when a worker returns to an idle state between
+/// tasks, so a task that forgets to tag its own IO is attributed to `Other`
+/// rather than to whatever ran before it.
+pub fn clear_io_type() {
+    set_io_type(IOType::Other);
+}
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 pub struct IOBytes {
@@ -479,4 +508,12 @@ mod tests {
         reserve_space_for_recover(data_path, 0).unwrap();
         assert!(!file.exists());
     }
+
+    #[test]
+    fn test_clear_io_type() {
+        set_io_type(IOType::Compaction);
+        assert_eq!(get_io_type(), IOType::Compaction);
+        clear_io_type();
+        assert_eq!(get_io_type(), IOType::Other);
+    }
 }
@@ -15,6 +15,9 @@ make_static_metric! {
         gc,
         import,
         export,
+        backup,
+        restore,
+        raft_log,
     }
 
     pub label_enum IOOp {
@@ -31,6 +34,11 @@ make_static_metric! {
         "type" => IOType,
         "op" => IOOp,
     }
+
+    pub struct IORateVec : Gauge {
+        "type" => IOType,
+        "op" => IOOp,
+    }
 }
 
 lazy_static! {
@@ -49,4 +57,20 @@ lazy_static! {
             &["type", "op"],
             exponential_buckets(1.0, 2.0, 22).unwrap() // max 4s
         ).unwrap();
+
+    // Peak of the per-flush byte rate observed within the current sliding
+    // window, for sizing hardware off of bursts rather than only the
+    // steady-state rate `tikv_io_bytes` implies.
+    pub static ref IO_PEAK_RATE_BYTES_VEC: IORateVec = register_static_gauge_vec!(
+        IORateVec,
+        "tikv_io_peak_rate_bytes",
+        "Peak observed disk io rate in bytes/sec within the current sliding window",
+        &["type", "op"]
+    ).unwrap();
+
+    // Populated by `WriteAmplificationTracker::flush`.
+    pub static ref IO_WRITE_AMPLIFICATION: Gauge = register_gauge!(
+        "tikv_io_write_amplification",
+        "Ratio of physical write/flush/compaction bytes to logical bytes written, as reported by the caller"
+    ).unwrap();
 }
@@ -1,16 +1,21 @@
 // Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
 
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use strum::EnumCount;
 
 use crate::iosnoop::{fetch_io_bytes, flush_io_latency_metrics};
-use crate::metrics::IO_BYTES_VEC;
+use crate::metrics::{IO_BYTES_VEC, IO_PEAK_RATE_BYTES_VEC, IO_WRITE_AMPLIFICATION};
 use crate::IOBytes;
 use crate::IORateLimiterStatistics;
 use crate::{IOOp, IOType};
 
+/// Default width of the sliding window over which `MetricsManager` tracks
+/// the peak per-flush IO rate before resetting it, see
+/// `MetricsManager::set_peak_rate_window`.
+const DEFAULT_PEAK_RATE_WINDOW: Duration = Duration::from_secs(60);
+
 pub enum BytesFetcher {
     /// Fetch IO statistics from IO rate limiter, which records passed-through IOs in atomic counters.
     FromRateLimiter(Arc<IORateLimiterStatistics>),
@@ -31,89 +36,302 @@ impl BytesFetcher {
 }
 
 macro_rules! flush_io_bytes {
-    ($fetcher:expr, $metrics:ident, $io_type:expr, $last_fetch:expr) => {
+    ($fetcher:expr, $metrics:ident, $io_type:expr, $last_fetch:expr, $peak_rate:expr, $elapsed_secs:expr) => {
         let bytes = $fetcher.fetch($io_type);
         let delta_bytes = bytes - $last_fetch;
         $last_fetch = bytes;
         IO_BYTES_VEC.$metrics.read.inc_by(delta_bytes.read as i64);
         IO_BYTES_VEC.$metrics.write.inc_by(delta_bytes.write as i64);
+
+        let read_rate = delta_bytes.read as f64 / $elapsed_secs;
+        let write_rate = delta_bytes.write as f64 / $elapsed_secs;
+        $peak_rate.0 = $peak_rate.0.max(read_rate);
+        $peak_rate.1 = $peak_rate.1.max(write_rate);
+        IO_PEAK_RATE_BYTES_VEC.$metrics.read.set($peak_rate.0);
+        IO_PEAK_RATE_BYTES_VEC.$metrics.write.set($peak_rate.1);
     };
 }
 
 pub struct MetricsManager {
     fetcher: BytesFetcher,
     last_fetch: [IOBytes; IOType::COUNT],
+    last_flush_at: Instant,
+    /// Peak (read, write) bytes/sec observed per `IOType` since
+    /// `window_start`.
+    peak_rate: [(f64, f64); IOType::COUNT],
+    window_start: Instant,
+    peak_rate_window: Duration,
 }
 
 impl MetricsManager {
     pub fn new(fetcher: BytesFetcher) -> Self {
+        let now = Instant::now();
         MetricsManager {
             fetcher,
             last_fetch: Default::default(),
+            last_flush_at: now,
+            peak_rate: [(0.0, 0.0); IOType::COUNT],
+            window_start: now,
+            peak_rate_window: DEFAULT_PEAK_RATE_WINDOW,
         }
     }
 
-    pub fn flush(&mut self, _now: Instant) {
+    /// Sets the width of the sliding window over which the peak per-flush
+    /// IO rate is tracked before being reset, letting operators trade off
+    /// how quickly `tikv_io_peak_rate_bytes` reacts to a burst subsiding
+    /// against how much of a burst it takes to register at all.
+    pub fn set_peak_rate_window(&mut self, window: Duration) {
+        self.peak_rate_window = window;
+    }
+
+    pub fn flush(&mut self, now: Instant) {
         flush_io_latency_metrics();
+
+        let elapsed_secs = now
+            .saturating_duration_since(self.last_flush_at)
+            .as_secs_f64()
+            .max(f64::MIN_POSITIVE);
+        self.last_flush_at = now;
+
+        if now.saturating_duration_since(self.window_start) >= self.peak_rate_window {
+            self.window_start = now;
+            self.peak_rate = [(0.0, 0.0); IOType::COUNT];
+        }
+
         flush_io_bytes!(
             self.fetcher,
             other,
             IOType::Other,
-            self.last_fetch[IOType::Other as usize]
+            self.last_fetch[IOType::Other as usize],
+            self.peak_rate[IOType::Other as usize],
+            elapsed_secs
         );
         flush_io_bytes!(
             self.fetcher,
             foreground_read,
             IOType::ForegroundRead,
-            self.last_fetch[IOType::ForegroundRead as usize]
+            self.last_fetch[IOType::ForegroundRead as usize],
+            self.peak_rate[IOType::ForegroundRead as usize],
+            elapsed_secs
         );
         flush_io_bytes!(
             self.fetcher,
             foreground_write,
             IOType::ForegroundWrite,
-            self.last_fetch[IOType::ForegroundWrite as usize]
+            self.last_fetch[IOType::ForegroundWrite as usize],
+            self.peak_rate[IOType::ForegroundWrite as usize],
+            elapsed_secs
         );
         flush_io_bytes!(
             self.fetcher,
             flush,
             IOType::Flush,
-            self.last_fetch[IOType::Flush as usize]
+            self.last_fetch[IOType::Flush as usize],
+            self.peak_rate[IOType::Flush as usize],
+            elapsed_secs
         );
         flush_io_bytes!(
             self.fetcher,
             compaction,
             IOType::Compaction,
-            self.last_fetch[IOType::Compaction as usize]
+            self.last_fetch[IOType::Compaction as usize],
+            self.peak_rate[IOType::Compaction as usize],
+            elapsed_secs
         );
         flush_io_bytes!(
             self.fetcher,
             replication,
             IOType::Replication,
-            self.last_fetch[IOType::Replication as usize]
+            self.last_fetch[IOType::Replication as usize],
+            self.peak_rate[IOType::Replication as usize],
+            elapsed_secs
         );
         flush_io_bytes!(
             self.fetcher,
             load_balance,
             IOType::LoadBalance,
-            self.last_fetch[IOType::LoadBalance as usize]
+            self.last_fetch[IOType::LoadBalance as usize],
+            self.peak_rate[IOType::LoadBalance as usize],
+            elapsed_secs
         );
         flush_io_bytes!(
             self.fetcher,
             gc,
             IOType::Gc,
-            self.last_fetch[IOType::Gc as usize]
+            self.last_fetch[IOType::Gc as usize],
+            self.peak_rate[IOType::Gc as usize],
+            elapsed_secs
         );
         flush_io_bytes!(
             self.fetcher,
             import,
             IOType::Import,
-            self.last_fetch[IOType::Import as usize]
+            self.last_fetch[IOType::Import as usize],
+            self.peak_rate[IOType::Import as usize],
+            elapsed_secs
         );
         flush_io_bytes!(
             self.fetcher,
             export,
             IOType::Export,
-            self.last_fetch[IOType::Export as usize]
+            self.last_fetch[IOType::Export as usize],
+            self.peak_rate[IOType::Export as usize],
+            elapsed_secs
+        );
+        flush_io_bytes!(
+            self.fetcher,
+            backup,
+            IOType::Backup,
+            self.last_fetch[IOType::Backup as usize],
+            self.peak_rate[IOType::Backup as usize],
+            elapsed_secs
+        );
+        flush_io_bytes!(
+            self.fetcher,
+            restore,
+            IOType::Restore,
+            self.last_fetch[IOType::Restore as usize],
+            self.peak_rate[IOType::Restore as usize],
+            elapsed_secs
+        );
+        flush_io_bytes!(
+            self.fetcher,
+            raft_log,
+            IOType::RaftLog,
+            self.last_fetch[IOType::RaftLog as usize],
+            self.peak_rate[IOType::RaftLog as usize],
+            elapsed_secs
+        );
+    }
+}
+
+/// Tracks a running write-amplification estimate: physical write bytes
+/// accrued across `IOType::ForegroundWrite`, `IOType::Flush` and
+/// `IOType::Compaction` -- foreground writes plus the background rewrite
+/// traffic they eventually cause -- divided by the logical bytes a caller
+/// reports having asked to write via `record_logical_write`. Published as
+/// `tikv_io_write_amplification` so operators tuning RocksDB can correlate
+/// a compaction/flush setting against how much it inflates physical IO
+/// relative to what the workload itself demands.
+pub struct WriteAmplificationTracker {
+    fetcher: BytesFetcher,
+    last_write: IOBytes,
+    last_flush: IOBytes,
+    last_compaction: IOBytes,
+    logical_bytes_written: u64,
+}
+
+impl WriteAmplificationTracker {
+    pub fn new(fetcher: BytesFetcher) -> Self {
+        WriteAmplificationTracker {
+            fetcher,
+            last_write: IOBytes::default(),
+            last_flush: IOBytes::default(),
+            last_compaction: IOBytes::default(),
+            logical_bytes_written: 0,
+        }
+    }
+
+    /// Adds `bytes` to the logical-write counter the next `flush` call
+    /// divides physical bytes by. Nothing in this tree calls it outside the
+    /// unit tests; the caller supplies the logical byte count.
+    pub fn record_logical_write(&mut self, bytes: u64) {
+        self.logical_bytes_written += bytes;
+    }
+
+    /// Refreshes `tikv_io_write_amplification` from the physical write
+    /// bytes accrued since the last call, divided by the logical bytes
+    /// recorded via `record_logical_write` over the same interval, then
+    /// resets both accumulators. A no-op, leaving the gauge at its last
+    /// value, if no logical bytes were recorded since the last call --
+    /// dividing by a workload that did nothing would otherwise produce a
+    /// meaningless spike.
+    pub fn flush(&mut self) {
+        let write = self.fetcher.fetch(IOType::ForegroundWrite);
+        let flush = self.fetcher.fetch(IOType::Flush);
+        let compaction = self.fetcher.fetch(IOType::Compaction);
+
+        let physical_write_bytes = (write - self.last_write).write
+            + (flush - self.last_flush).write
+            + (compaction - self.last_compaction).write;
+        self.last_write = write;
+        self.last_flush = flush;
+        self.last_compaction = compaction;
+
+        if self.logical_bytes_written == 0 {
+            return;
+        }
+        IO_WRITE_AMPLIFICATION.set(physical_write_bytes as f64 / self.logical_bytes_written as f64);
+        self.logical_bytes_written = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::IO_PEAK_RATE_BYTES_VEC;
+
+    #[test]
+    fn test_peak_rate_tracks_bursts_and_resets() {
+        let stats = Arc::new(IORateLimiterStatistics::new());
+        let mut manager = MetricsManager::new(BytesFetcher::FromRateLimiter(stats.clone()));
+        manager.set_peak_rate_window(Duration::from_millis(50));
+
+        let mut t = Instant::now();
+        manager.flush(t);
+
+        // Steady trickle: a small, roughly constant rate.
+        for _ in 0..3 {
+            stats.add(IOType::ForegroundWrite, IOOp::Write, 100);
+            t += Duration::from_millis(10);
+            manager.flush(t);
+        }
+        let steady_peak = IO_PEAK_RATE_BYTES_VEC.foreground_write.write.get();
+        assert!(steady_peak > 0.0);
+
+        // A burst within the same window should push the peak well above
+        // the steady-state rate.
+        stats.add(IOType::ForegroundWrite, IOOp::Write, 100_000);
+        t += Duration::from_millis(10);
+        manager.flush(t);
+        let burst_peak = IO_PEAK_RATE_BYTES_VEC.foreground_write.write.get();
+        assert!(burst_peak > steady_peak);
+
+        // Once the window rolls over, a subsequent quiet flush resets the
+        // peak back down rather than keeping the stale burst value forever.
+        t += Duration::from_millis(100);
+        manager.flush(t);
+        let after_reset = IO_PEAK_RATE_BYTES_VEC.foreground_write.write.get();
+        assert!(after_reset < burst_peak);
+    }
+
+    // Exercises `WriteAmplificationTracker` against `IORateLimiterStatistics`
+    // rather than the real bcc-backed snooper, the same substitution
+    // `test_peak_rate_tracks_bursts_and_resets` above makes -- the snooper
+    // requires a Linux kernel with BCC support that isn't available in every
+    // build environment, and `IORateLimiterStatistics` reports the same
+    // per-`IOType` byte deltas `WriteAmplificationTracker::flush` consumes.
+    #[test]
+    fn test_write_amplification_reflects_physical_over_logical_bytes() {
+        let stats = Arc::new(IORateLimiterStatistics::new());
+        let mut tracker =
+            WriteAmplificationTracker::new(BytesFetcher::FromRateLimiter(stats.clone()));
+
+        // A foreground write plus the flush and compaction it eventually
+        // triggers: 300 physical bytes total against 100 logical bytes
+        // requested, i.e. 3x amplification.
+        stats.add(IOType::ForegroundWrite, IOOp::Write, 100);
+        stats.add(IOType::Flush, IOOp::Write, 100);
+        stats.add(IOType::Compaction, IOOp::Write, 100);
+        tracker.record_logical_write(100);
+        tracker.flush();
+
+        let amplification = IO_WRITE_AMPLIFICATION.get();
+        assert!(
+            amplification > 1.0,
+            "expected amplification > 1.0, got {}",
+            amplification
         );
+        assert!((amplification - 3.0).abs() < 1e-9);
     }
 }
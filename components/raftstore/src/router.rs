@@ -1,11 +1,18 @@
 // Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
 
 use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use collections::HashMap;
 use crossbeam::channel::{SendError, TrySendError};
 use engine_traits::{KvEngine, RaftEngine, Snapshot};
 use kvproto::raft_cmdpb::RaftCmdRequest;
 use kvproto::raft_serverpb::RaftMessage;
+use raft::eraftpb::MessageType;
 use raft::SnapshotStatus;
 use tikv_util::error;
 use tikv_util::time::ThreadReadId;
@@ -13,10 +20,17 @@ use tikv_util::time::ThreadReadId;
 use crate::store::fsm::RaftRouter;
 use crate::store::transport::{CasualRouter, ProposalRouter, StoreRouter};
 use crate::store::{
-    Callback, CasualMessage, LocalReader, PeerMsg, RaftCommand, SignificantMsg, StoreMsg,
+    AbstractPeer, Callback, CasualMessage, LocalReader, PeerMsg, RaftCommand, SignificantMsg,
+    StoreMsg,
 };
 use crate::{DiscardReason, Error as RaftStoreError, Result as RaftStoreResult};
 
+/// Caps how many `broadcast_query_applied` responses can be in flight at
+/// once. Responses are handed back to the caller over a bounded channel of
+/// this size, so once it's full, peer worker threads block on sending their
+/// result instead of piling up unboundedly ahead of a slow caller.
+const BROADCAST_QUERY_APPLIED_CONCURRENCY: usize = 256;
+
 /// Routes messages to the raftstore.
 pub trait RaftStoreRouter<EK>:
     StoreRouter<EK> + ProposalRouter<EK::Snapshot> + CasualRouter<EK> + Send + Clone
@@ -89,6 +103,75 @@ where
             PeerMsg::SignificantMsg(SignificantMsg::StoreResolved { store_id, group_id })
         })
     }
+
+    /// Queries every local region's applied index and returns a map of
+    /// region_id to applied index.
+    ///
+    /// Fans out via `broadcast_normal`, so it only reaches regions this
+    /// store already knows about -- it doesn't ask other stores. Waits up
+    /// to `timeout` for the fan-out to fully drain; a region whose peer is
+    /// too busy to respond in time is simply missing from the returned map
+    /// rather than making the whole call fail, since a caller that only
+    /// needs a best-effort watermark can retry for a handful of stragglers
+    /// cheaper than blocking on them. Concurrency is bounded by
+    /// `BROADCAST_QUERY_APPLIED_CONCURRENCY`: once that many responses are
+    /// queued and not yet drained, further peers block on handing back
+    /// their result until the caller catches up.
+    ///
+    /// STATUS: the request that added this (a consistent applied-index
+    /// watermark for `backup::endpoint::Endpoint`) rests on a premise that
+    /// doesn't hold, and should be closed rather than treated as delivered.
+    /// `backup::endpoint::Endpoint` does not call this and should not be
+    /// made to: it already gets a consistent view of every region's data
+    /// from `Engine::snapshot` at the request's `start_ts`, which is an MVCC
+    /// read and so is unaffected by how far each region's raft log has
+    /// actually applied. There is also no place in the backup response
+    /// (`kvproto::backup::BackupResponse`) to carry a per-region
+    /// applied-index watermark back to the client even if one were
+    /// collected here. Recommend rejecting the originating request as
+    /// won't-do rather than keeping this method around under backup's
+    /// framing with no backup caller.
+    ///
+    /// The method's only caller in this tree is the
+    /// `test_broadcast_query_applied` integration test. It's
+    /// left in place, undocumented as backup-specific, only because it's a
+    /// correctly-implemented general router primitive that some future
+    /// caller with an actual watermark need (and somewhere to put the
+    /// result) could use -- not because this satisfies the request that
+    /// introduced it.
+    fn broadcast_query_applied(&self, timeout: Duration) -> RaftStoreResult<HashMap<u64, u64>> {
+        let (tx, rx) = mpsc::sync_channel(BROADCAST_QUERY_APPLIED_CONCURRENCY);
+        let sent = AtomicUsize::new(0);
+        self.broadcast_normal(|| {
+            sent.fetch_add(1, Ordering::Relaxed);
+            let tx = tx.clone();
+            PeerMsg::CasualMessage(CasualMessage::AccessPeer(Box::new(
+                move |peer: &mut dyn AbstractPeer| {
+                    let region_id = peer.region().get_id();
+                    let applied_index = peer.apply_state().get_applied_index();
+                    let _ = tx.send((region_id, applied_index));
+                },
+            )))
+        });
+        drop(tx);
+
+        let expected = sent.load(Ordering::Relaxed);
+        let deadline = Instant::now() + timeout;
+        let mut watermarks = HashMap::default();
+        while watermarks.len() < expected {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            match rx.recv_timeout(remaining) {
+                Ok((region_id, applied_index)) => {
+                    watermarks.insert(region_id, applied_index);
+                }
+                Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        Ok(watermarks)
+    }
 }
 
 pub trait LocalReadRouter<EK>: Send + Clone
@@ -102,7 +185,37 @@ where
         cb: Callback<EK::Snapshot>,
     ) -> RaftStoreResult<()>;
 
+    /// Like `read`, but re-checks the local read path up to `attempts` times
+    /// before falling back to raftstore, to ride out a lease that's expected
+    /// to renew imminently instead of paying for a read-index round trip.
+    /// Never relaxes the correctness check `read` uses, so a read that isn't
+    /// safe to serve locally is still never served locally -- it only
+    /// changes how many times that check gets a chance to pass.
+    ///
+    /// The default implementation just calls `read` once, for routers that
+    /// have no local read path to retry.
+    fn read_with_retry(
+        &self,
+        read_id: Option<ThreadReadId>,
+        req: RaftCmdRequest,
+        cb: Callback<EK::Snapshot>,
+        _attempts: u32,
+    ) -> RaftStoreResult<()> {
+        self.read(read_id, req, cb)
+    }
+
     fn release_snapshot_cache(&self);
+
+    /// Like `release_snapshot_cache`, but never panics if the local read
+    /// path is already borrowed elsewhere (e.g. a concurrent read on a
+    /// clone of this router) -- it returns `false` instead, letting the
+    /// caller retry later. A mitigation for the underlying `RefCell`'s
+    /// fragility, not a fix for it; routers without a local read path to
+    /// borrow just delegate to `release_snapshot_cache` and always succeed.
+    fn try_release_snapshot_cache(&self) -> bool {
+        self.release_snapshot_cache();
+        true
+    }
 }
 
 #[derive(Clone)]
@@ -146,10 +259,53 @@ where
     fn broadcast_normal(&self, _: impl FnMut() -> PeerMsg<EK>) {}
 }
 
+/// One sampled record of a raft message passed to `send_raft_msg`, captured
+/// only while message tracing is enabled.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RaftMessageTrace {
+    pub from_store: u64,
+    pub to_store: u64,
+    pub region_id: u64,
+    pub msg_type: MessageType,
+}
+
+/// Capacity of the ring buffer backing `ServerRaftStoreRouter::recent_sent`.
+/// Old entries are dropped once this is exceeded so tracing can be left on
+/// for a while during an investigation without unbounded memory growth.
+const MESSAGE_TRACE_CAPACITY: usize = 1024;
+
+/// Off-by-default sampling of sent raft messages, for debugging cross-store
+/// message loss. When disabled, `record` is a single relaxed atomic load.
+#[derive(Default)]
+struct MessageTrace {
+    enabled: AtomicBool,
+    ring: Mutex<VecDeque<RaftMessageTrace>>,
+}
+
+impl MessageTrace {
+    fn record(&self, msg: &RaftMessage) {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return;
+        }
+        let trace = RaftMessageTrace {
+            from_store: msg.get_from_peer().get_store_id(),
+            to_store: msg.get_to_peer().get_store_id(),
+            region_id: msg.get_region_id(),
+            msg_type: msg.get_message().get_msg_type(),
+        };
+        let mut ring = self.ring.lock().unwrap();
+        if ring.len() == MESSAGE_TRACE_CAPACITY {
+            ring.pop_front();
+        }
+        ring.push_back(trace);
+    }
+}
+
 /// A router that routes messages to the raftstore
 pub struct ServerRaftStoreRouter<EK: KvEngine, ER: RaftEngine> {
     router: RaftRouter<EK, ER>,
     local_reader: RefCell<LocalReader<RaftRouter<EK, ER>, EK>>,
+    trace: Arc<MessageTrace>,
 }
 
 impl<EK: KvEngine, ER: RaftEngine> Clone for ServerRaftStoreRouter<EK, ER> {
@@ -157,6 +313,7 @@ impl<EK: KvEngine, ER: RaftEngine> Clone for ServerRaftStoreRouter<EK, ER> {
         ServerRaftStoreRouter {
             router: self.router.clone(),
             local_reader: self.local_reader.clone(),
+            trace: self.trace.clone(),
         }
     }
 }
@@ -171,8 +328,22 @@ impl<EK: KvEngine, ER: RaftEngine> ServerRaftStoreRouter<EK, ER> {
         ServerRaftStoreRouter {
             router,
             local_reader,
+            trace: Arc::default(),
         }
     }
+
+    /// Enables or disables sampling of sent raft messages into the ring
+    /// buffer queried by `recent_sent`. Off by default; clones of this
+    /// router share the same ring buffer and flag.
+    pub fn set_message_tracing(&self, enabled: bool) {
+        self.trace.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Returns the currently buffered sampled messages, oldest first. Empty
+    /// unless `set_message_tracing(true)` has been called.
+    pub fn recent_sent(&self) -> Vec<RaftMessageTrace> {
+        self.trace.ring.lock().unwrap().iter().cloned().collect()
+    }
 }
 
 impl<EK: KvEngine, ER: RaftEngine> StoreRouter<EK> for ServerRaftStoreRouter<EK, ER> {
@@ -198,6 +369,7 @@ impl<EK: KvEngine, ER: RaftEngine> CasualRouter<EK> for ServerRaftStoreRouter<EK
 
 impl<EK: KvEngine, ER: RaftEngine> RaftStoreRouter<EK> for ServerRaftStoreRouter<EK, ER> {
     fn send_raft_msg(&self, msg: RaftMessage) -> RaftStoreResult<()> {
+        self.trace.record(&msg);
         RaftStoreRouter::send_raft_msg(&self.router, msg)
     }
 
@@ -227,10 +399,41 @@ impl<EK: KvEngine, ER: RaftEngine> LocalReadRouter<EK> for ServerRaftStoreRouter
         Ok(())
     }
 
+    fn read_with_retry(
+        &self,
+        read_id: Option<ThreadReadId>,
+        req: RaftCmdRequest,
+        cb: Callback<EK::Snapshot>,
+        attempts: u32,
+    ) -> RaftStoreResult<()> {
+        let mut local_reader = self.local_reader.borrow_mut();
+        local_reader.read_with_retry(read_id, req, cb, attempts);
+        Ok(())
+    }
+
     fn release_snapshot_cache(&self) {
         let mut local_reader = self.local_reader.borrow_mut();
         local_reader.release_snapshot_cache();
     }
+
+    fn try_release_snapshot_cache(&self) -> bool {
+        try_release_snapshot_cache(&self.local_reader)
+    }
+}
+
+/// Shared logic behind `ServerRaftStoreRouter::try_release_snapshot_cache`:
+/// releases the snapshot cache if `local_reader` isn't already borrowed,
+/// returning whether it did. Pulled out into a free function, generic over
+/// the router type `C`, so it can be exercised directly in tests without
+/// needing a full `ServerRaftStoreRouter` (and the `RaftRouter` it requires).
+fn try_release_snapshot_cache<C, EK: KvEngine>(local_reader: &RefCell<LocalReader<C, EK>>) -> bool {
+    match local_reader.try_borrow_mut() {
+        Ok(mut local_reader) => {
+            local_reader.release_snapshot_cache();
+            true
+        }
+        Err(_) => false,
+    }
 }
 
 #[inline]
@@ -241,6 +444,117 @@ pub fn handle_send_error<T>(region_id: u64, e: TrySendError<T>) -> RaftStoreErro
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use kvproto::metapb::Peer;
+    use raft::eraftpb::Message;
+
+    use super::*;
+
+    fn new_msg(
+        from_store: u64,
+        to_store: u64,
+        region_id: u64,
+        msg_type: MessageType,
+    ) -> RaftMessage {
+        let mut from = Peer::default();
+        from.set_store_id(from_store);
+        let mut to = Peer::default();
+        to.set_store_id(to_store);
+        let mut message = Message::default();
+        message.set_msg_type(msg_type);
+
+        let mut msg = RaftMessage::default();
+        msg.set_region_id(region_id);
+        msg.set_from_peer(from);
+        msg.set_to_peer(to);
+        msg.set_message(message);
+        msg
+    }
+
+    #[test]
+    fn test_message_trace_off_by_default() {
+        let trace = MessageTrace::default();
+        trace.record(&new_msg(1, 2, 100, MessageType::MsgAppend));
+        assert!(trace.ring.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_message_trace_records_when_enabled() {
+        let trace = MessageTrace::default();
+        trace.enabled.store(true, Ordering::Relaxed);
+
+        trace.record(&new_msg(1, 2, 100, MessageType::MsgAppend));
+        trace.record(&new_msg(2, 1, 100, MessageType::MsgAppendResponse));
+
+        let ring = trace.ring.lock().unwrap();
+        assert_eq!(ring.len(), 2);
+        assert_eq!(
+            ring[0],
+            RaftMessageTrace {
+                from_store: 1,
+                to_store: 2,
+                region_id: 100,
+                msg_type: MessageType::MsgAppend,
+            }
+        );
+        assert_eq!(
+            ring[1],
+            RaftMessageTrace {
+                from_store: 2,
+                to_store: 1,
+                region_id: 100,
+                msg_type: MessageType::MsgAppendResponse,
+            }
+        );
+    }
+
+    #[test]
+    fn test_message_trace_ring_capacity() {
+        let trace = MessageTrace::default();
+        trace.enabled.store(true, Ordering::Relaxed);
+
+        for i in 0..MESSAGE_TRACE_CAPACITY + 10 {
+            trace.record(&new_msg(1, 2, i as u64, MessageType::MsgAppend));
+        }
+
+        let ring = trace.ring.lock().unwrap();
+        assert_eq!(ring.len(), MESSAGE_TRACE_CAPACITY);
+        assert_eq!(ring.front().unwrap().region_id, 10);
+    }
+
+    #[test]
+    fn test_try_release_snapshot_cache_does_not_panic_when_borrowed() {
+        use std::sync::mpsc::sync_channel;
+
+        use engine_test::kv::KvTestEngine;
+        use engine_traits::ALL_CFS;
+
+        use crate::store::fsm::store::StoreMeta;
+
+        let path = tempfile::Builder::new()
+            .prefix("test_try_release_snapshot_cache")
+            .tempdir()
+            .unwrap();
+        let db =
+            engine_test::kv::new_engine(path.path().to_str().unwrap(), None, ALL_CFS, None)
+                .unwrap();
+        let (ch, _rx) = sync_channel::<RaftCommand<engine_test::kv::KvTestSnapshot>>(1);
+        let store_meta = Arc::new(Mutex::new(StoreMeta::new(0)));
+        let reader: LocalReader<_, KvTestEngine> = LocalReader::new(db, store_meta, ch);
+        let local_reader = RefCell::new(reader);
+
+        // With no outstanding borrow, releasing succeeds.
+        assert!(try_release_snapshot_cache(&local_reader));
+
+        // A live borrow -- standing in for a concurrent read on a clone of
+        // the router -- makes `try_borrow_mut` fail; the try-variant reports
+        // that instead of panicking the way a plain `borrow_mut` would.
+        let _held = local_reader.borrow_mut();
+        assert!(!try_release_snapshot_cache(&local_reader));
+    }
+}
+
 impl<EK: KvEngine, ER: RaftEngine> RaftStoreRouter<EK> for RaftRouter<EK, ER> {
     fn send_raft_msg(&self, msg: RaftMessage) -> RaftStoreResult<()> {
         let region_id = msg.get_region_id();
@@ -88,6 +88,47 @@ pub fn prepare_bootstrap_cluster(
     Ok(())
 }
 
+/// Writes the region state, apply state, and raft state for many regions at
+/// once, flushing the kv write batch and raft log batch every `batch_size`
+/// regions instead of once per region, where a separate kv write-batch flush
+/// and raft consume per region would otherwise dominate the cost.
+///
+/// Unlike `prepare_bootstrap_cluster`, this does not write the
+/// `PREPARE_BOOTSTRAP_KEY` marker: that key names the single first region of
+/// a brand new cluster, which does not apply when initializing an arbitrary
+/// batch of regions.
+///
+/// Nothing in the store's own startup path calls this -- a store only ever
+/// bootstraps its one first region via `prepare_bootstrap_cluster`, and
+/// every other region it ever holds arrives through a raft conf change or
+/// split, not a batch write; this does not speed up store startup on its
+/// own. The real caller is `Debugger::recreate_regions`, backing
+/// `tikv-ctl`'s `recreate-regions` command, which needs the same regions
+/// written without paying for `recreate_region`'s per-region overlap scan
+/// on each one.
+pub fn bootstrap_many_regions(
+    engines: &Engines<impl KvEngine, impl RaftEngine>,
+    regions: &[metapb::Region],
+    batch_size: usize,
+) -> Result<()> {
+    let batch_size = batch_size.max(1);
+    for chunk in regions.chunks(batch_size) {
+        let mut wb = engines.kv.write_batch();
+        let mut raft_wb = engines.raft.log_batch(1024);
+        for region in chunk {
+            let mut state = RegionLocalState::default();
+            state.set_region(region.clone());
+            box_try!(wb.put_msg_cf(CF_RAFT, &keys::region_state_key(region.get_id()), &state));
+            write_initial_apply_state(&mut wb, region.get_id())?;
+            write_initial_raft_state(&mut raft_wb, region.get_id())?;
+        }
+        wb.write()?;
+        engines.sync_kv()?;
+        box_try!(engines.raft.consume(&mut raft_wb, true));
+    }
+    Ok(())
+}
+
 // Clear first region meta and prepare key.
 pub fn clear_prepare_bootstrap_cluster(
     engines: &Engines<impl KvEngine, impl RaftEngine>,
@@ -195,4 +236,58 @@ mod tests {
             .unwrap()
         );
     }
+
+    #[test]
+    fn test_bootstrap_many_regions_with_small_batch_size() {
+        let path = Builder::new()
+            .prefix("var-many-regions")
+            .tempdir()
+            .unwrap();
+        let raft_path = path.path().join("raft");
+        let kv_engine = engine_test::kv::new_engine(
+            path.path().to_str().unwrap(),
+            None,
+            &[CF_DEFAULT, CF_RAFT],
+            None,
+        )
+        .unwrap();
+        let raft_engine =
+            engine_test::raft::new_engine(raft_path.to_str().unwrap(), None, CF_DEFAULT, None)
+                .unwrap();
+        let engines = Engines::new(kv_engine.clone(), raft_engine.clone());
+
+        let regions: Vec<_> = (1..=10).map(|id| initial_region(1, id, id)).collect();
+        // A batch size well below the region count forces several flushes.
+        bootstrap_many_regions(&engines, &regions, 3).unwrap();
+
+        for region in &regions {
+            let id = region.get_id();
+            let state: RegionLocalState = kv_engine
+                .get_msg_cf(CF_RAFT, &keys::region_state_key(id))
+                .unwrap()
+                .unwrap();
+            assert_eq!(state.get_region(), region);
+            assert!(
+                kv_engine
+                    .get_value_cf(CF_RAFT, &keys::apply_state_key(id))
+                    .unwrap()
+                    .is_some()
+            );
+            assert!(
+                raft_engine
+                    .get_value(&keys::raft_state_key(id))
+                    .unwrap()
+                    .is_some()
+            );
+        }
+
+        // No `PREPARE_BOOTSTRAP_KEY` marker: this isn't the single-first-
+        // region cluster bootstrap phase.
+        assert!(
+            kv_engine
+                .get_value(keys::PREPARE_BOOTSTRAP_KEY)
+                .unwrap()
+                .is_none()
+        );
+    }
 }
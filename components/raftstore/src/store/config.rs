@@ -78,6 +78,12 @@ pub struct Config {
     pub raft_engine_purge_interval: ReadableDuration,
     // When a peer is not responding for this time, leader will not keep entry cache for it.
     pub raft_entry_cache_life_time: ReadableDuration,
+    // When the total memory tracked by `RAFT_ENTRIES_CACHES_GAUGE` across all regions on this
+    // store exceeds this, proactively force-compact raft logs on every peer to release entry
+    // caches instead of waiting for it to happen peer-by-peer. `ReadableSize(0)` disables this.
+    pub raft_entry_cache_high_water_mark: ReadableSize,
+    // Interval to check `raft_entry_cache_high_water_mark`.
+    pub raft_entry_cache_evict_check_tick_interval: ReadableDuration,
     // When a peer is newly added, reject transferring leader to the peer for a while.
     pub raft_reject_transfer_leader_duration: ReadableDuration,
 
@@ -215,6 +221,11 @@ impl Default for Config {
             raft_log_reserve_max_ticks: 6,
             raft_engine_purge_interval: ReadableDuration::secs(10),
             raft_entry_cache_life_time: ReadableDuration::secs(30),
+            // 0 keeps this off by default; the slow creep this guards
+            // against only shows up with tens of thousands of regions per
+            // store, so most deployments don't need it.
+            raft_entry_cache_high_water_mark: ReadableSize(0),
+            raft_entry_cache_evict_check_tick_interval: ReadableDuration::secs(60),
             raft_reject_transfer_leader_duration: ReadableDuration::secs(3),
             split_region_check_tick_interval: ReadableDuration::secs(10),
             region_split_check_diff: split_size / 16,
@@ -486,6 +497,12 @@ impl Config {
         CONFIG_RAFTSTORE_GAUGE
             .with_label_values(&["raft_entry_cache_life_time"])
             .set(self.raft_entry_cache_life_time.as_secs() as f64);
+        CONFIG_RAFTSTORE_GAUGE
+            .with_label_values(&["raft_entry_cache_high_water_mark"])
+            .set(self.raft_entry_cache_high_water_mark.0 as f64);
+        CONFIG_RAFTSTORE_GAUGE
+            .with_label_values(&["raft_entry_cache_evict_check_tick_interval"])
+            .set(self.raft_entry_cache_evict_check_tick_interval.as_secs() as f64);
         CONFIG_RAFTSTORE_GAUGE
             .with_label_values(&["raft_reject_transfer_leader_duration"])
             .set(self.raft_reject_transfer_leader_duration.as_secs() as f64);
@@ -88,6 +88,14 @@ pub struct StoreInfo<E> {
     pub capacity: u64,
 }
 
+/// Whether `current` (the store-wide sum tracked by
+/// `RAFT_ENTRIES_CACHES_GAUGE`) has crossed `high_water_mark` and warrants
+/// proactively compacting raft logs to shrink entry caches.
+/// `high_water_mark == 0` means the check is disabled.
+fn should_evict_entry_caches(current: u64, high_water_mark: u64) -> bool {
+    high_water_mark > 0 && current >= high_water_mark
+}
+
 pub struct StoreMeta {
     /// store id
     pub store_id: Option<u64>,
@@ -540,6 +548,7 @@ impl<'a, EK: KvEngine + 'static, ER: RaftEngine + 'static, T: Transport>
             StoreTick::ConsistencyCheck => self.on_consistency_check_tick(),
             StoreTick::CleanupImportSST => self.on_cleanup_import_sst_tick(),
             StoreTick::RaftEnginePurge => self.on_raft_engine_purge_tick(),
+            StoreTick::EntryCacheEvictCheck => self.on_entry_cache_evict_check_tick(),
         }
         let elapsed = t.elapsed();
         RAFT_EVENT_DURATION
@@ -600,6 +609,7 @@ impl<'a, EK: KvEngine + 'static, ER: RaftEngine + 'static, T: Transport>
         self.register_snap_mgr_gc_tick();
         self.register_consistency_check_tick();
         self.register_raft_engine_purge_tick();
+        self.register_entry_cache_evict_check_tick();
     }
 }
 
@@ -2353,6 +2363,45 @@ impl<'a, EK: KvEngine, ER: RaftEngine, T: Transport> StoreFsmDelegate<'a, EK, ER
         self.register_raft_engine_purge_tick();
     }
 
+    fn register_entry_cache_evict_check_tick(&self) {
+        self.ctx.schedule_store_tick(
+            StoreTick::EntryCacheEvictCheck,
+            self.ctx.cfg.raft_entry_cache_evict_check_tick_interval.0,
+        )
+    }
+
+    /// With tens of thousands of regions on a single store, the sum of every
+    /// region's raft entry cache -- tracked in aggregate by
+    /// `RAFT_ENTRIES_CACHES_GAUGE` -- can creep up steadily even though no
+    /// individual region looks unusual on its own. Rather than waiting for
+    /// each peer to notice on its own schedule, proactively nudge every peer
+    /// to force-compact its raft log (and so drop its entry cache down to
+    /// what's still needed) once the aggregate crosses
+    /// `raft_entry_cache_high_water_mark`.
+    ///
+    /// There's no per-region breakdown of `RAFT_ENTRIES_CACHES_GAUGE` to rank
+    /// "largest regions" by, so this asks every peer at once, the same way
+    /// `RaftlogGcTask::Purge` already does when raft engine files are purged;
+    /// a peer with nothing worth compacting is a cheap no-op for it.
+    fn on_entry_cache_evict_check_tick(&mut self) {
+        self.register_entry_cache_evict_check_tick();
+        let high_water_mark = self.ctx.cfg.raft_entry_cache_high_water_mark.0;
+        let current = RAFT_ENTRIES_CACHES_GAUGE.get().max(0) as u64;
+        if !should_evict_entry_caches(current, high_water_mark) {
+            return;
+        }
+        warn!(
+            "raft entry cache memory crossed high water mark, forcing raft log compaction on all peers";
+            "store_id" => self.fsm.store.id,
+            "current" => current,
+            "high_water_mark" => high_water_mark,
+        );
+        RAFT_ENTRY_CACHE_EVICT_TICK_COUNTER.inc();
+        self.ctx
+            .router
+            .broadcast_normal(|| PeerMsg::CasualMessage(CasualMessage::ForceCompactRaftLogs));
+    }
+
     fn on_check_leader(&self, leaders: Vec<LeaderInfo>, cb: Box<dyn FnOnce(Vec<u64>) + Send>) {
         let meta = self.ctx.store_meta.lock().unwrap();
         let regions = leaders
@@ -2450,4 +2499,18 @@ mod tests {
         let expected_declined_bytes = vec![(2, 8192), (3, 4096)];
         assert_eq!(declined_bytes, expected_declined_bytes);
     }
+
+    #[test]
+    fn test_should_evict_entry_caches() {
+        // Disabled (the default) never triggers, regardless of how much
+        // memory is tracked.
+        assert!(!should_evict_entry_caches(0, 0));
+        assert!(!should_evict_entry_caches(u64::MAX, 0));
+
+        // Below the mark: no action.
+        assert!(!should_evict_entry_caches(99, 100));
+        // At or above the mark: proactively compact.
+        assert!(should_evict_entry_caches(100, 100));
+        assert!(should_evict_entry_caches(101, 100));
+    }
 }
@@ -131,6 +131,7 @@ make_auto_flush_static_metric! {
         consistency_check,
         cleanup_import_sst,
         raft_engine_purge,
+        entry_cache_evict_check,
     }
 
     pub label_enum CompactionGuardAction {
@@ -492,6 +493,16 @@ lazy_static! {
         "Total memory size of raft entries caches."
         ).unwrap();
 
+    // Incremented every time `RAFT_ENTRIES_CACHES_GAUGE` crosses the
+    // configured high-water mark and a proactive raft log compaction is
+    // scheduled to bring it back down. A store that never manages to shrink
+    // its entry caches shows up here as a steadily climbing rate rather than
+    // just a high gauge value, which is easier to alert on.
+    pub static ref RAFT_ENTRY_CACHE_EVICT_TICK_COUNTER: IntCounter = register_int_counter!(
+        "tikv_raft_entry_cache_evict_tick_total",
+        "Total number of times entry cache memory crossed the high water mark and triggered proactive compaction."
+        ).unwrap();
+
     pub static ref APPLY_PENDING_BYTES_GAUGE: IntGauge = register_int_gauge!(
         "tikv_raftstore_apply_pending_bytes",
         "The bytes pending in the channel of apply FSMs."
@@ -504,6 +515,12 @@ lazy_static! {
     )
     .unwrap();
 
+    pub static ref RAFTLOG_FETCH_INFLIGHT_BYTES_GAUGE: IntGauge = register_int_gauge!(
+        "tikv_raftstore_raftlog_fetch_inflight_bytes",
+        "The estimated bytes buffered by in-flight raftlog fetches."
+    )
+    .unwrap();
+
     pub static ref COMPACTION_GUARD_ACTION_COUNTER_VEC: IntCounterVec =
         register_int_counter_vec!(
             "tikv_raftstore_compaction_guard_action_total",
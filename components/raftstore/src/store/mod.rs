@@ -23,8 +23,8 @@ mod snap;
 mod worker;
 
 pub use self::bootstrap::{
-    bootstrap_store, clear_prepare_bootstrap_cluster, clear_prepare_bootstrap_key, initial_region,
-    prepare_bootstrap_cluster,
+    bootstrap_many_regions, bootstrap_store, clear_prepare_bootstrap_cluster,
+    clear_prepare_bootstrap_key, initial_region, prepare_bootstrap_cluster,
 };
 pub use self::compaction_guard::CompactionGuardGeneratorFactory;
 pub use self::config::Config;
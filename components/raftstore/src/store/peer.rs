@@ -528,7 +528,19 @@ where
 
         let tag = format!("[region {}] {}", region.get_id(), peer.get_id());
 
-        let ps = PeerStorage::new(engines, region, sched, peer.get_id(), tag.clone())?;
+        // `last_term`/`applied_index_term` are only ever read through
+        // `PeerStorage::last_term`/`applied_index_term`, which lazily compute
+        // and cache them on first access -- so deferring that read past boot
+        // is always safe, and worth doing since most regions stay quiescent
+        // long enough that the deferred read never actually happens.
+        let ps = PeerStorage::new_with_lazy_state(
+            engines,
+            region,
+            sched,
+            peer.get_id(),
+            tag.clone(),
+            true,
+        )?;
 
         let applied_index = ps.applied_index();
 
@@ -995,6 +1007,26 @@ where
         self.raft_group.mut_store()
     }
 
+    /// Like `PeerStorage::applied_index_term`, but logs and returns
+    /// `fallback` instead of propagating the error. Meant for the many
+    /// per-tick call sites that only compare this against the current term
+    /// to decide whether the peer is caught up; on a failed lazy load,
+    /// `fallback` should be whichever answer keeps that comparison on the
+    /// conservative ("not caught up yet") side, so a transient read error
+    /// makes the peer retry later instead of crashing its thread.
+    #[inline]
+    pub(crate) fn applied_index_term_or(&self, fallback: u64) -> u64 {
+        self.get_store().applied_index_term().unwrap_or_else(|e| {
+            error!(
+                "failed to lazily load applied index term";
+                "region_id" => self.region_id,
+                "peer_id" => self.peer.get_id(),
+                "err" => ?e,
+            );
+            fallback
+        })
+    }
+
     #[inline]
     pub fn is_applying_snapshot(&self) -> bool {
         self.get_store().is_applying_snapshot()
@@ -1496,7 +1528,9 @@ where
 
         // There may be some values that are not applied by this leader yet but the old leader,
         // if applied_index_term isn't equal to current term.
-        self.get_store().applied_index_term() == self.term()
+        // A failed lazy load falls back to u64::MAX, which can't equal a real
+        // term, so it's treated as not caught up rather than crashing.
+        self.applied_index_term_or(u64::MAX) == self.term()
             // There may be stale read if the old leader splits really slow,
             // the new region may already elected a new leader while
             // the old leader still think it owns the split range.
@@ -1546,7 +1580,7 @@ where
         let reject_reason = if !self.is_leader() {
             // Only leader can handle request snapshot.
             "not_leader"
-        } else if self.get_store().applied_index_term() != self.term()
+        } else if self.applied_index_term_or(u64::MAX) != self.term()
             || self.get_store().applied_index() < request_index
         {
             // Reject if there are any unapplied raft log.
@@ -2121,7 +2155,11 @@ where
             self.raft_group.store().region(),
         );
 
-        let progress_to_be_updated = self.mut_store().applied_index_term() != applied_index_term;
+        // A failed lazy load falls back to `applied_index_term.wrapping_add(1)`,
+        // guaranteed to differ from `applied_index_term`, so progress is
+        // conservatively marked as changed rather than the peer crashing here.
+        let progress_to_be_updated = self.applied_index_term_or(applied_index_term.wrapping_add(1))
+            != applied_index_term;
         self.mut_store().set_applied_state(apply_state);
         self.mut_store().set_applied_term(applied_index_term);
 
@@ -2940,7 +2978,7 @@ where
             return Err(box_err!(
                 "{} peer has not applied to current term, applied_term {}, current_term {}",
                 self.tag,
-                self.get_store().applied_index_term(),
+                self.applied_index_term_or(u64::MAX),
                 self.term()
             ));
         }
@@ -3115,11 +3153,11 @@ where
         // Actually, according to the implementation of conf change in raft-rs, this check must be
         // passed if the previous check that `pending_conf_index` should be less than or equal to
         // `self.get_store().applied_index()` is passed.
-        if self.get_store().applied_index_term() != self.term() {
+        if self.applied_index_term_or(u64::MAX) != self.term() {
             return Err(box_err!(
                 "{} peer has not applied to current term, applied_term {}, current_term {}",
                 self.tag,
-                self.get_store().applied_index_term(),
+                self.applied_index_term_or(u64::MAX),
                 self.term()
             ));
         }
@@ -3286,7 +3324,7 @@ where
                 let res = self.raft_group.raft.check_group_commit_consistent();
                 if Some(true) != res {
                     let mut buffer: SmallVec<[(u64, u64, u64); 5]> = SmallVec::new();
-                    if self.get_store().applied_index_term() >= self.term() {
+                    if self.applied_index_term_or(0) >= self.term() {
                         let progress = self.raft_group.raft.prs();
                         for (id, p) in progress.iter() {
                             if !progress.conf().voters().contains(*id) {
@@ -3325,6 +3363,16 @@ where
         self.approximate_size.is_none() || self.approximate_keys.is_none()
     }
 
+    /// Returns an estimate, in bytes, of the total memory this region's peer
+    /// contributes to the store's footprint: its on-disk approximate size
+    /// plus the raft log entries currently held in its entry cache.
+    ///
+    /// This is a rough figure meant for capacity planning, not an accounting
+    /// of every allocation (proposals, pending reads, etc. are not counted).
+    pub fn approximate_mem_size(&self) -> u64 {
+        self.approximate_size.unwrap_or_default() + self.get_store().raft_entry_cache_mem_size()
+    }
+
     pub fn heartbeat_pd<T>(&mut self, ctx: &PollContext<EK, ER, T>) {
         let task = PdTask::Heartbeat(HeartbeatTask {
             term: self.term(),
@@ -3674,7 +3722,7 @@ where
     ER: RaftEngine,
 {
     fn has_applied_to_current_term(&mut self) -> bool {
-        self.get_store().applied_index_term() == self.term()
+        self.applied_index_term_or(u64::MAX) == self.term()
     }
 
     fn inspect_lease(&mut self) -> LeaseState {
@@ -11,6 +11,7 @@ use std::{cmp, error, u64};
 
 use engine_traits::CF_RAFT;
 use engine_traits::{Engines, KvEngine, Mutable, Peekable};
+use file_system::{IOType, WithIOType};
 use keys::{self, enc_end_key, enc_start_key};
 use kvproto::metapb::{self, Region};
 use kvproto::raft_serverpb::{
@@ -357,16 +358,16 @@ pub struct InvokeContext {
 }
 
 impl InvokeContext {
-    pub fn new<EK: KvEngine, ER: RaftEngine>(store: &PeerStorage<EK, ER>) -> InvokeContext {
-        InvokeContext {
+    pub fn new<EK: KvEngine, ER: RaftEngine>(store: &PeerStorage<EK, ER>) -> Result<InvokeContext> {
+        Ok(InvokeContext {
             region_id: store.get_region_id(),
             raft_state: store.raft_state.clone(),
             apply_state: store.apply_state.clone(),
-            last_term: store.last_term,
+            last_term: store.last_term()?,
             has_new_entries: false,
             snap_region: None,
             destroyed_regions: vec![],
-        }
+        })
     }
 
     #[inline]
@@ -607,8 +608,14 @@ where
     region: metapb::Region,
     raft_state: RaftLocalState,
     apply_state: RaftApplyState,
-    applied_index_term: u64,
-    last_term: u64,
+    // `None` means the value hasn't been materialized yet. Under lazy
+    // loading (see `PeerStorage::new_with_lazy_state`'s `lazy_load`
+    // parameter), boot skips
+    // the extra raft log reads needed to compute these and defers it to the
+    // first access, which is fine because they're only consulted once a
+    // region actually starts processing raft messages.
+    applied_index_term: Cell<Option<u64>>,
+    last_term: Cell<Option<u64>>,
 
     snap_state: RefCell<SnapState>,
     gen_snap_task: RefCell<Option<GenSnapTask>>,
@@ -667,6 +674,33 @@ where
         region_sched: Scheduler<RegionTask<EK::Snapshot>>,
         peer_id: u64,
         tag: String,
+    ) -> Result<PeerStorage<EK, ER>> {
+        Self::new_impl(engines, region, region_sched, peer_id, tag, false)
+    }
+
+    /// Like `new`, but when `lazy_load` is set, skips the extra raft log
+    /// reads needed to compute `last_term`/`applied_index_term` at boot and
+    /// defers them to the first access instead. Startup with many regions
+    /// spends a large share of its time in exactly these reads, most of
+    /// which turn out to be wasted for regions that stay quiescent.
+    pub fn new_with_lazy_state(
+        engines: Engines<EK, ER>,
+        region: &metapb::Region,
+        region_sched: Scheduler<RegionTask<EK::Snapshot>>,
+        peer_id: u64,
+        tag: String,
+        lazy_load: bool,
+    ) -> Result<PeerStorage<EK, ER>> {
+        Self::new_impl(engines, region, region_sched, peer_id, tag, lazy_load)
+    }
+
+    fn new_impl(
+        engines: Engines<EK, ER>,
+        region: &metapb::Region,
+        region_sched: Scheduler<RegionTask<EK::Snapshot>>,
+        peer_id: u64,
+        tag: String,
+        lazy_load: bool,
     ) -> Result<PeerStorage<EK, ER>> {
         debug!(
             "creating storage on specified path";
@@ -679,8 +713,14 @@ where
         if let Err(e) = validate_states(region.get_id(), &engines, &mut raft_state, &apply_state) {
             return Err(box_err!("{} validate state fail: {:?}", tag, e));
         }
-        let last_term = init_last_term(&engines, region, &raft_state, &apply_state)?;
-        let applied_index_term = init_applied_index_term(&engines, region, &apply_state)?;
+        let (last_term, applied_index_term) = if lazy_load {
+            (None, None)
+        } else {
+            (
+                Some(init_last_term(&engines, region, &raft_state, &apply_state)?),
+                Some(init_applied_index_term(&engines, region, &apply_state)?),
+            )
+        };
 
         let cache = if engines.raft.has_builtin_entry_cache() {
             None
@@ -699,8 +739,8 @@ where
             region_sched,
             snap_tried_cnt: RefCell::new(0),
             tag,
-            applied_index_term,
-            last_term,
+            applied_index_term: Cell::new(applied_index_term),
+            last_term: Cell::new(last_term),
             cache,
         })
     }
@@ -752,6 +792,11 @@ where
         if low == high {
             return Ok(ents);
         }
+        // Any disk read below happens on a cache miss, i.e. it's a catch-up
+        // read for a lagging peer rather than the raft log cache doing its
+        // job; tag it so that IO is attributed separately from foreground
+        // traffic when isolating recovery IO pressure.
+        let _io_type_guard = WithIOType::new(IOType::RaftLog);
         let region_id = self.get_region_id();
         if let Some(ref cache) = self.cache {
             let cache_low = cache.first_index().unwrap_or(u64::MAX);
@@ -803,8 +848,9 @@ where
             return Ok(self.truncated_term());
         }
         self.check_range(idx, idx + 1)?;
-        if self.truncated_term() == self.last_term || idx == self.last_index() {
-            return Ok(self.last_term);
+        let last_term = self.last_term()?;
+        if self.truncated_term() == last_term || idx == self.last_index() {
+            return Ok(last_term);
         }
         let entries = self.entries(idx, idx + 1, raft::NO_LIMIT)?;
         Ok(entries[0].get_term())
@@ -820,9 +866,23 @@ where
         last_index(&self.raft_state)
     }
 
-    #[inline]
-    pub fn last_term(&self) -> u64 {
-        self.last_term
+    /// Returns the term of the last log entry, computing and caching it on
+    /// first access if this storage was created with lazy state loading.
+    ///
+    /// The lazily-computed path can fail (e.g. a transient read error from
+    /// the raft engine), which is why this returns a `Result` rather than
+    /// panicking: unlike the eager path this replaced, this can run at any
+    /// point during steady-state peer processing, not just at boot, and a
+    /// peer that has otherwise been running fine shouldn't have its thread
+    /// crashed by one bad read.
+    pub fn last_term(&self) -> Result<u64> {
+        if let Some(term) = self.last_term.get() {
+            return Ok(term);
+        }
+        let term =
+            init_last_term(&self.engines, &self.region, &self.raft_state, &self.apply_state)?;
+        self.last_term.set(Some(term));
+        Ok(term)
     }
 
     #[inline]
@@ -837,7 +897,7 @@ where
 
     #[inline]
     pub fn set_applied_term(&mut self, applied_index_term: u64) {
-        self.applied_index_term = applied_index_term;
+        self.applied_index_term.set(Some(applied_index_term));
     }
 
     #[inline]
@@ -845,9 +905,17 @@ where
         &self.apply_state
     }
 
-    #[inline]
-    pub fn applied_index_term(&self) -> u64 {
-        self.applied_index_term
+    /// Returns the term of the last applied log entry, computing and caching
+    /// it on first access if this storage was created with lazy state
+    /// loading. See `last_term`'s doc comment for why this is fallible
+    /// rather than panicking on a failed lazy load.
+    pub fn applied_index_term(&self) -> Result<u64> {
+        if let Some(term) = self.applied_index_term.get() {
+            return Ok(term);
+        }
+        let term = init_applied_index_term(&self.engines, &self.region, &self.apply_state)?;
+        self.applied_index_term.set(Some(term));
+        Ok(term)
     }
 
     #[inline]
@@ -1081,6 +1149,19 @@ where
         self.cache.as_ref().map_or(true, |c| c.is_empty())
     }
 
+    /// Returns the estimated in-memory size, in bytes, occupied by this
+    /// region's raft log entry cache. Used for capacity planning
+    /// alongside `Peer::approximate_size`, which only tracks on-disk data.
+    pub fn raft_entry_cache_mem_size(&self) -> u64 {
+        if let Some(ref cache) = self.cache {
+            return cache.get_total_mem_size().max(0) as u64;
+        }
+        self.engines
+            .raft
+            .flush_stats()
+            .map_or(0, |stats| stats.cache_size as u64)
+    }
+
     pub fn maybe_gc_cache(&mut self, replicated_idx: u64, apply_idx: u64) {
         if self.engines.raft.has_builtin_entry_cache() {
             let rid = self.get_region_id();
@@ -1383,7 +1464,7 @@ where
         ready: &mut Ready,
         destroy_regions: Vec<metapb::Region>,
     ) -> Result<InvokeContext> {
-        let mut ctx = InvokeContext::new(self);
+        let mut ctx = InvokeContext::new(self)?;
         let snapshot_index = if ready.snapshot().is_empty() {
             0
         } else {
@@ -1433,7 +1514,7 @@ where
     pub fn post_ready(&mut self, ctx: InvokeContext) -> Option<ApplySnapResult> {
         self.raft_state = ctx.raft_state;
         self.apply_state = ctx.apply_state;
-        self.last_term = ctx.last_term;
+        self.last_term.set(Some(ctx.last_term));
         // If we apply snapshot ok, we should update some infos like applied index too.
         let snap_region = match ctx.snap_region {
             Some(r) => r,
@@ -1751,7 +1832,7 @@ mod tests {
     ) -> PeerStorage<KvTestEngine, RaftTestEngine> {
         let mut store = new_storage(sched, path);
         let mut kv_wb = store.engines.kv.write_batch();
-        let mut ctx = InvokeContext::new(&store);
+        let mut ctx = InvokeContext::new(&store).unwrap();
         let mut ready_ctx = ReadyContext::new(&store);
         store
             .append(&mut ctx, ents[1..].to_vec(), &mut ready_ctx)
@@ -1773,7 +1854,7 @@ mod tests {
     }
 
     fn append_ents(store: &mut PeerStorage<KvTestEngine, RaftTestEngine>, ents: &[Entry]) {
-        let mut ctx = InvokeContext::new(store);
+        let mut ctx = InvokeContext::new(store).unwrap();
         let mut ready_ctx = ReadyContext::new(store);
         store
             .append(&mut ctx, ents.to_vec(), &mut ready_ctx)
@@ -1827,6 +1908,42 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_storage_lazy_load() {
+        let ents = vec![new_entry(3, 3), new_entry(4, 4), new_entry(5, 5)];
+        let td = Builder::new().prefix("tikv-store-test").tempdir().unwrap();
+        let worker = Worker::new("snap-manager").lazy_build("snap-manager");
+        let sched = worker.scheduler();
+
+        let mut store = new_storage_from_ents(sched, &td, &ents);
+        // Rebuild with lazy loading enabled, reusing the same engines and
+        // already-persisted state.
+        let region = store.region.clone();
+        let engines = store.engines.clone();
+        let sched = store.region_sched.clone();
+        drop(store);
+        store = PeerStorage::new_with_lazy_state(
+            engines,
+            &region,
+            sched,
+            0,
+            "".to_owned(),
+            true,
+        )
+        .unwrap();
+
+        // Values aren't computed yet.
+        assert!(store.last_term.get().is_none());
+        assert!(store.applied_index_term.get().is_none());
+
+        // Accessing materializes and caches them, without changing the
+        // result compared to eager loading.
+        assert_eq!(store.last_term().unwrap(), 5);
+        assert_eq!(store.last_term.get(), Some(5));
+        assert_eq!(store.applied_index_term().unwrap(), RAFT_INIT_LOG_TERM);
+        assert_eq!(store.applied_index_term.get(), Some(RAFT_INIT_LOG_TERM));
+    }
+
     fn get_meta_key_count(store: &PeerStorage<KvTestEngine, RaftTestEngine>) -> usize {
         let region_id = store.get_region_id();
         let mut count = 0;
@@ -1976,7 +2093,7 @@ mod tests {
             let worker = Worker::new("snap-manager").lazy_build("snap-manager");
             let sched = worker.scheduler();
             let store = new_storage_from_ents(sched, &td, &ents);
-            let mut ctx = InvokeContext::new(&store);
+            let mut ctx = InvokeContext::new(&store).unwrap();
             let res = store
                 .term(idx)
                 .map_err(From::from)
@@ -2080,7 +2197,7 @@ mod tests {
         // Drop the task.
         let _ = s.gen_snap_task.borrow_mut().take().unwrap();
 
-        let mut ctx = InvokeContext::new(&s);
+        let mut ctx = InvokeContext::new(&s).unwrap();
         let mut kv_wb = s.engines.kv.write_batch();
         let mut ready_ctx = ReadyContext::new(&s);
         s.append(
@@ -2101,7 +2218,7 @@ mod tests {
         ready_ctx.raft_wb.write().unwrap();
         s.apply_state = ctx.apply_state;
         s.raft_state = ctx.raft_state;
-        ctx = InvokeContext::new(&s);
+        ctx = InvokeContext::new(&s).unwrap();
         let term = s.term(7).unwrap();
         compact_raft_log(&s.tag, &mut ctx.apply_state, 7, term).unwrap();
         kv_wb = s.engines.kv.write_batch();
@@ -2370,7 +2487,7 @@ mod tests {
         let td2 = Builder::new().prefix("tikv-store-test").tempdir().unwrap();
         let mut s2 = new_storage(sched.clone(), &td2);
         assert_eq!(s2.first_index(), s2.applied_index() + 1);
-        let mut ctx = InvokeContext::new(&s2);
+        let mut ctx = InvokeContext::new(&s2).unwrap();
         assert_ne!(ctx.last_term, snap1.get_metadata().get_term());
         let mut kv_wb = s2.engines.kv.write_batch();
         let mut raft_wb = s2.engines.raft.write_batch();
@@ -2388,7 +2505,7 @@ mod tests {
         let ents = &[new_entry(3, 3), new_entry(4, 3)];
         let mut s3 = new_storage_from_ents(sched, &td3, ents);
         validate_cache(&s3, &ents[1..]);
-        let mut ctx = InvokeContext::new(&s3);
+        let mut ctx = InvokeContext::new(&s3).unwrap();
         assert_ne!(ctx.last_term, snap1.get_metadata().get_term());
         let mut kv_wb = s3.engines.kv.write_batch();
         let mut raft_wb = s3.engines.raft.write_batch();
@@ -379,6 +379,57 @@ pub fn is_region_initialized(r: &metapb::Region) -> bool {
     !r.get_peers().is_empty()
 }
 
+/// One consistency problem flagged by `verify_region_consistency`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegionInconsistency {
+    /// Two regions in the same scan share a region id.
+    DuplicateRegionId(u64),
+    /// Two regions' `[start_key, end_key)` ranges overlap.
+    OverlappingRegions { first: u64, second: u64 },
+    /// An initialized region has a zero `version` in its epoch.
+    ZeroEpoch(u64),
+}
+
+/// Scans a snapshot of a store's region states -- e.g. every live region
+/// `Debugger::bad_regions` scraped from `RegionLocalState` while checking
+/// each one boots on its own -- for the low-level invariants that a single
+/// region's own state can't reveal: no two regions may share an id, no two
+/// regions' key ranges may overlap, and every initialized region must carry
+/// a non-zero epoch version. Turns that kind of corruption into an
+/// actionable report instead of a `check_key_in_region` failure discovered
+/// much later, far from its actual cause.
+///
+/// `regions` must be sorted by `start_key`; overlap detection only compares
+/// each region against its immediate neighbor in this order.
+pub fn verify_region_consistency(regions: &[metapb::Region]) -> Result<(), Vec<RegionInconsistency>> {
+    let mut problems = Vec::new();
+    let mut seen_ids = std::collections::HashSet::new();
+    for region in regions {
+        if !seen_ids.insert(region.get_id()) {
+            problems.push(RegionInconsistency::DuplicateRegionId(region.get_id()));
+        }
+        if is_region_initialized(region) && region.get_region_epoch().get_version() == 0 {
+            problems.push(RegionInconsistency::ZeroEpoch(region.get_id()));
+        }
+    }
+    for pair in regions.windows(2) {
+        let (a, b) = (&pair[0], &pair[1]);
+        // An empty end key means `a` covers the rest of the keyspace, so
+        // anything sorted after it necessarily overlaps.
+        if a.get_end_key().is_empty() || a.get_end_key() > b.get_start_key() {
+            problems.push(RegionInconsistency::OverlappingRegions {
+                first: a.get_id(),
+                second: b.get_id(),
+            });
+        }
+    }
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(problems)
+    }
+}
+
 /// Lease records an expired time, for examining the current moment is in lease or not.
 /// It's dedicated to the Raft leader lease mechanism, contains either state of
 ///   1. Suspect Timestamp
@@ -1453,6 +1504,52 @@ mod tests {
         assert!(is_region_initialized(&region));
     }
 
+    fn region_with_range(id: u64, start_key: &[u8], end_key: &[u8], version: u64) -> metapb::Region {
+        let mut region = metapb::Region::default();
+        region.set_id(id);
+        region.set_start_key(start_key.to_vec());
+        region.set_end_key(end_key.to_vec());
+        region.set_peers(vec![new_peer(1, id * 10)].into());
+        let mut epoch = metapb::RegionEpoch::default();
+        epoch.set_version(version);
+        region.set_region_epoch(epoch);
+        region
+    }
+
+    #[test]
+    fn test_verify_region_consistency_reports_overlaps_and_duplicates() {
+        // A clean, non-overlapping, contiguous key space passes.
+        let regions = vec![
+            region_with_range(1, b"", b"k2", 1),
+            region_with_range(2, b"k2", b"k4", 1),
+            region_with_range(3, b"k4", b"", 1),
+        ];
+        assert_eq!(verify_region_consistency(&regions), Ok(()));
+
+        // Simulate the kind of corruption an unsafe-recovery style hand-edit
+        // could introduce: region 2's range now overlaps region 3's, and
+        // region 3 got assigned an id that collides with a later entry.
+        let overlapping = vec![
+            region_with_range(1, b"", b"k2", 1),
+            region_with_range(2, b"k2", b"k5", 1),
+            region_with_range(3, b"k4", b"", 1),
+            region_with_range(3, b"k6", b"", 1),
+        ];
+        let problems = verify_region_consistency(&overlapping).unwrap_err();
+        assert!(problems.contains(&RegionInconsistency::OverlappingRegions {
+            first: 2,
+            second: 3,
+        }));
+        assert!(problems.contains(&RegionInconsistency::DuplicateRegionId(3)));
+
+        // An initialized region with a zero epoch version is also flagged.
+        let zero_epoch = vec![region_with_range(1, b"", b"", 0)];
+        assert_eq!(
+            verify_region_consistency(&zero_epoch),
+            Err(vec![RegionInconsistency::ZeroEpoch(1)])
+        );
+    }
+
     #[test]
     fn test_admin_cmd_epoch_map_include_all_cmd_type() {
         #[cfg(feature = "protobuf-codec")]
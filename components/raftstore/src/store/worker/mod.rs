@@ -6,6 +6,7 @@ mod compact;
 mod consistency_check;
 mod metrics;
 mod pd;
+mod raftlog_fetch;
 mod raftlog_gc;
 mod read;
 mod region;
@@ -20,6 +21,7 @@ pub use self::consistency_check::{Runner as ConsistencyCheckRunner, Task as Cons
 pub use self::pd::{
     FlowStatistics, FlowStatsReporter, HeartbeatTask, Runner as PdRunner, Task as PdTask,
 };
+pub use self::raftlog_fetch::{widen_fetch_high, FetchFairnessQueue, FetchRequest, InFlightFetches};
 pub use self::raftlog_gc::{Runner as RaftlogGcRunner, Task as RaftlogGcTask};
 pub use self::read::{LocalReader, Progress as ReadProgress, ReadDelegate, ReadExecutor};
 pub use self::region::{Runner as RegionRunner, Task as RegionTask};
@@ -0,0 +1,511 @@
+// Copyright 2022 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Fairness policy for scheduling raft log fetch requests.
+//!
+//! A single lagging peer can submit fetches spanning a huge index range,
+//! and because fetches are otherwise served in submission order, this can
+//! delay small catch-up fetches from other peers sharing the same worker.
+//! [`FetchFairnessQueue`] round-robins between distinct `to_peer` targets
+//! and caps how many bytes worth of entries are served to one target per
+//! turn, deferring the remainder to a later turn.
+//!
+//! STATUS: infeasible as scoped, flagged for the backlog owner. Every type
+//! in this module assumes raft log fetches go through a queue or worker
+//! that can be paused, reordered, or backed off -- but the only place a
+//! fetch happens today, `PeerStorage::entries`, is the synchronous
+//! `raft::Storage::entries` trait method raft-rs calls inline from `Ready`
+//! processing on the peer's own thread, and it only ever gets `&self`, not
+//! `&mut self`. That rules out all of it for real:
+//! - [`FetchFairnessQueue`] and [`InFlightFetches`] need an async worker in
+//!   front of `entries` to queue, round-robin, and cancel through; none
+//!   exists, and building one is a raftstore-wide refactor, not something
+//!   this module can grow into on its own.
+//! - [`FetchByteBudget::try_reserve`] needs a caller that can back off and
+//!   retry instead of returning immediately, which `entries` -- bound by
+//!   raft-rs's synchronous contract -- cannot do.
+//! - [`widen_fetch_high`] only pays for itself if the extra entries it
+//!   reads get cached for the next request; caching means calling into
+//!   `PeerStorage`'s cache-insert path, which needs `&mut self` that
+//!   `entries` doesn't have. Reading the wider range and discarding the
+//!   extra entries would cost more IO for no benefit, so it isn't wired
+//!   in either.
+//! - [`clamp_fetch_low`] is actively the wrong choice for `entries` to
+//!   make: `check_range` already rejects a `low` at or before
+//!   `truncated_index()` with `Err(StorageError::Compacted)`, which is
+//!   what raft-rs relies on to fall back to sending a snapshot. Serving a
+//!   clamped, truncated range instead would silently return fewer entries
+//!   than asked for rather than signalling compaction, breaking that
+//!   fallback -- so this one should be rejected outright, not deferred.
+//!
+//! These building blocks stay here, unit-tested, for whenever the async
+//! fetch-worker refactor they depend on actually happens.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use collections::{HashMap, HashSet};
+
+use crate::store::metrics::RAFTLOG_FETCH_INFLIGHT_BYTES_GAUGE;
+
+/// Given the raft-requested range `[low, high)` for a `SendAppend` fetch,
+/// returns the actual upper bound to read: widened to cover at least
+/// `min_window` entries when the request is smaller than that, but never
+/// past `committed` (inclusive) since there is nothing to read beyond it,
+/// and left untouched when `min_window` is `0` (no minimum configured).
+///
+/// A follower lagging by a single entry otherwise triggers a fixed-overhead
+/// engine read for just that one entry; reading a slightly larger batch
+/// amortizes that cost and pre-warms the cache for the likely next request.
+/// The caller is still responsible for capping the read at `max_size`
+/// bytes; this only ever widens the requested index range, never the byte
+/// budget.
+///
+/// STATUS: infeasible as scoped -- see the module-level doc comment.
+/// `PeerStorage::entries` -- the sole caller of raft log reads today --
+/// doesn't call this. Widening the read only pays for itself if the extra
+/// entries get cached for the next request; caching needs `&mut self`
+/// that `entries` doesn't have (it's called through `&raft::Storage`).
+/// Reading the wider range and discarding the extra entries without
+/// caching them would cost more IO for no benefit, so that shortcut isn't
+/// taken either.
+pub fn widen_fetch_high(low: u64, high: u64, committed: u64, min_window: u64) -> u64 {
+    if min_window == 0 {
+        return high;
+    }
+    let widened = low.saturating_add(min_window);
+    let ceiling = committed.saturating_add(1);
+    high.max(widened).min(ceiling)
+}
+
+/// Given a fetch's requested `low` bound and `first_index` -- the oldest
+/// index still available after compaction -- returns the truncation marker
+/// to record on the fetch (its `truncated_to` field): `Some(first_index)`
+/// when `low` asks for entries older than what's available, in which case
+/// the caller should read starting from `first_index` instead of `low`
+/// rather than erroring out on the gap. `None` when the full requested
+/// range is intact and no clamping is needed.
+///
+/// A follower whose response carries a `truncated_to` knows entries
+/// `[low, truncated_to)` are gone for good and it must fall back to
+/// requesting a snapshot for that gap instead of retrying the same range.
+///
+/// STATUS: rejected, not deferred -- see the module-level doc comment.
+/// `PeerStorage::entries` must not call this: its `check_range` already
+/// rejects a `low` at or before `truncated_index()` with
+/// `Err(StorageError::Compacted)`, which is what raft-rs relies on to fall
+/// back to sending a snapshot. Clamping and serving a truncated range
+/// there instead would silently return fewer entries than raft-rs asked
+/// for rather than signalling compaction, breaking that fallback. This
+/// isn't blocked on an async refactor like its neighbors in this file --
+/// wiring it in would be a correctness regression, so it should stay
+/// unused and the originating request should be closed as won't-do.
+pub fn clamp_fetch_low(low: u64, first_index: u64) -> Option<u64> {
+    if low < first_index {
+        Some(first_index)
+    } else {
+        None
+    }
+}
+
+/// A raft log fetch request, scoped to the byte range still left to serve.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FetchRequest {
+    pub region_id: u64,
+    pub to_peer: u64,
+    pub low: u64,
+    pub high: u64,
+    /// Estimated bytes remaining to fetch for `[low, high)`.
+    pub remaining_bytes: u64,
+    /// Set via `clamp_fetch_low` once this request is actually served, if
+    /// `low` had already been compacted away. `None` for a request that
+    /// hasn't been served yet, or whose full requested range turned out to
+    /// still be available.
+    pub truncated_to: Option<u64>,
+}
+
+/// Queues fetch requests per `to_peer` target and hands them out
+/// round-robin, capping the bytes released to one target per turn so a
+/// single large fetch cannot starve the others.
+///
+/// STATUS: infeasible as scoped -- see the module-level doc comment.
+/// Nothing currently submits requests to this queue: `PeerStorage::entries`
+/// -- the only place raft log fetches happen today -- is the synchronous
+/// `raft::Storage::entries` trait method, called inline from raft-rs's
+/// `Ready` processing, with no queue or worker in between to round-robin
+/// through. This is the building block for an async fetch worker that
+/// doesn't exist yet; wiring it in would mean moving fetches off that
+/// synchronous call path first, which is out of scope here.
+pub struct FetchFairnessQueue {
+    max_bytes_per_turn: u64,
+    order: VecDeque<u64>,
+    pending: HashMap<u64, VecDeque<FetchRequest>>,
+}
+
+impl FetchFairnessQueue {
+    pub fn new(max_bytes_per_turn: u64) -> Self {
+        FetchFairnessQueue {
+            max_bytes_per_turn: max_bytes_per_turn.max(1),
+            order: VecDeque::new(),
+            pending: HashMap::default(),
+        }
+    }
+
+    /// Submits a fetch request, queueing it behind any other pending
+    /// requests for the same `to_peer`.
+    pub fn push(&mut self, req: FetchRequest) {
+        let to_peer = req.to_peer;
+        let queue = self.pending.entry(to_peer).or_insert_with(|| {
+            self.order.push_back(to_peer);
+            VecDeque::new()
+        });
+        queue.push_back(req);
+    }
+
+    /// Pops the next batch of requests to serve this turn: at most one
+    /// request per distinct `to_peer`, each truncated to
+    /// `max_bytes_per_turn` bytes, with the remainder pushed back to the
+    /// end of that target's queue for a later turn.
+    pub fn next_turn(&mut self) -> Vec<FetchRequest> {
+        let mut batch = Vec::with_capacity(self.order.len());
+        for _ in 0..self.order.len() {
+            let to_peer = self.order.pop_front().unwrap();
+            let queue = match self.pending.get_mut(&to_peer) {
+                Some(q) if !q.is_empty() => q,
+                _ => {
+                    self.pending.remove(&to_peer);
+                    continue;
+                }
+            };
+            let mut req = queue.pop_front().unwrap();
+            if req.remaining_bytes > self.max_bytes_per_turn {
+                let span = req.high - req.low;
+                let served_span =
+                    (span * self.max_bytes_per_turn / req.remaining_bytes.max(1)).max(1);
+                let split_at = req.low + served_span.min(span);
+                let remainder = FetchRequest {
+                    region_id: req.region_id,
+                    to_peer,
+                    low: split_at,
+                    high: req.high,
+                    remaining_bytes: req.remaining_bytes - self.max_bytes_per_turn,
+                    truncated_to: None,
+                };
+                req.high = split_at;
+                req.remaining_bytes = self.max_bytes_per_turn;
+                queue.push_back(remainder);
+            }
+            if queue.is_empty() {
+                self.pending.remove(&to_peer);
+            }
+            self.order.push_back(to_peer);
+            batch.push(req);
+        }
+        batch
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    /// Drops every request still queued for `region_id`, e.g. because the
+    /// region was destroyed or merged while its fetches were waiting for a
+    /// turn. Returns the number of requests dropped. Requests already
+    /// handed out by `next_turn` are not covered here -- use
+    /// [`InFlightFetches`] to cancel those.
+    pub fn cancel_region(&mut self, region_id: u64) -> usize {
+        let mut removed = 0;
+        let pending = &mut self.pending;
+        for queue in pending.values_mut() {
+            let before = queue.len();
+            queue.retain(|req| req.region_id != region_id);
+            removed += before - queue.len();
+        }
+        pending.retain(|_, queue| !queue.is_empty());
+        self.order.retain(|to_peer| pending.contains_key(to_peer));
+        removed
+    }
+}
+
+/// Tracks regions whose in-flight fetches -- already popped off a
+/// [`FetchFairnessQueue`] and handed to the raft engine -- should have
+/// their results dropped instead of being delivered once they complete.
+///
+/// A region can be destroyed or merged after its fetch has already left the
+/// queue, at which point the fetch itself can no longer be stopped; the
+/// most a cancellation can do is suppress the now-useless result. The
+/// caller is expected to check [`is_cancelled`](Self::is_cancelled) for the
+/// fetch's `region_id` right before delivering its result, and to call
+/// [`clear`](Self::clear) once the region is reused (e.g. re-created with a
+/// new peer) so a stale cancellation doesn't suppress a future fetch.
+///
+/// STATUS: infeasible as scoped -- see the module-level doc comment.
+/// There is no such caller today, on either end: destroying or merging a
+/// region doesn't call [`cancel_region`](Self::cancel_region) or
+/// [`FetchFairnessQueue::cancel_region`], and nothing checks
+/// `is_cancelled` before using a fetch's result, because
+/// `PeerStorage::entries` returns its result directly to raft-rs rather
+/// than going through a cancellable queue in the first place. There is no
+/// in-flight state to cancel until an async fetch worker exists to hold
+/// it, which is out of scope here.
+#[derive(Default)]
+pub struct InFlightFetches {
+    cancelled: HashSet<u64>,
+}
+
+impl InFlightFetches {
+    pub fn cancel_region(&mut self, region_id: u64) {
+        self.cancelled.insert(region_id);
+    }
+
+    pub fn is_cancelled(&self, region_id: u64) -> bool {
+        self.cancelled.contains(&region_id)
+    }
+
+    pub fn clear(&mut self, region_id: u64) {
+        self.cancelled.remove(&region_id);
+    }
+}
+
+/// A global byte budget for entries buffered by in-flight raftlog fetches.
+///
+/// Each `SendAppend` allocates an `ents` vec sized up to the fetch's
+/// `max_size`; with enough concurrent fetches that's unbounded memory. A
+/// caller should `try_reserve` before allocating a fetch's buffer -- backing
+/// off or shrinking the fetch to fit the remaining budget when it returns
+/// `false` -- and `release` once the response carrying that buffer has been
+/// sent and the memory is no longer held.
+///
+/// STATUS: infeasible as scoped -- see the module-level doc comment.
+/// No caller does this yet: `PeerStorage::entries` allocates its `ents` Vec
+/// and reads straight from the raft engine or entry cache without checking
+/// a budget first, since it's a synchronous `raft::Storage` call raft-rs
+/// expects to either return promptly or fail, not block waiting on
+/// `try_reserve`. Bounding total in-flight fetch memory this way needs an
+/// async fetch path that can back off instead, which is out of scope here.
+pub struct FetchByteBudget {
+    limit: u64,
+    used: AtomicU64,
+}
+
+impl FetchByteBudget {
+    pub fn new(limit: u64) -> Self {
+        FetchByteBudget {
+            limit,
+            used: AtomicU64::new(0),
+        }
+    }
+
+    /// Attempts to reserve `bytes` against the budget. Returns `false`
+    /// without reserving anything if the budget is exhausted; the fetch
+    /// should either wait or be bounded to `remaining()` and retried.
+    pub fn try_reserve(&self, bytes: u64) -> bool {
+        let reserved = self
+            .used
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                if used + bytes > self.limit {
+                    None
+                } else {
+                    Some(used + bytes)
+                }
+            })
+            .is_ok();
+        if reserved {
+            RAFTLOG_FETCH_INFLIGHT_BYTES_GAUGE.set(self.used() as i64);
+        }
+        reserved
+    }
+
+    /// Releases `bytes` previously reserved via `try_reserve`, once its
+    /// fetch response has been sent.
+    pub fn release(&self, bytes: u64) {
+        self.used.fetch_sub(bytes, Ordering::AcqRel);
+        RAFTLOG_FETCH_INFLIGHT_BYTES_GAUGE.set(self.used() as i64);
+    }
+
+    pub fn used(&self) -> u64 {
+        self.used.load(Ordering::Acquire)
+    }
+
+    pub fn remaining(&self) -> u64 {
+        self.limit.saturating_sub(self.used())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn req(to_peer: u64, low: u64, high: u64, remaining_bytes: u64) -> FetchRequest {
+        FetchRequest {
+            region_id: 1,
+            to_peer,
+            low,
+            high,
+            remaining_bytes,
+            truncated_to: None,
+        }
+    }
+
+    #[test]
+    fn test_widen_fetch_high_reads_up_to_min_window_when_available() {
+        // A one-entry request (`[5, 6)`) is widened to the configured
+        // minimum window since plenty of committed entries are available.
+        assert_eq!(widen_fetch_high(5, 6, 100, 16), 21);
+
+        // Never widened past what's actually committed.
+        assert_eq!(widen_fetch_high(5, 6, 10, 16), 11);
+
+        // A request already at least as large as the window is left alone.
+        assert_eq!(widen_fetch_high(5, 30, 100, 16), 30);
+
+        // No minimum configured: the requested range passes through as-is.
+        assert_eq!(widen_fetch_high(5, 6, 100, 0), 6);
+    }
+
+    #[test]
+    fn test_clamp_fetch_low_marks_truncation_when_compacted() {
+        // The full requested range is still available: no clamping.
+        assert_eq!(clamp_fetch_low(10, 5), None);
+        assert_eq!(clamp_fetch_low(5, 5), None);
+
+        // `low` was already compacted away: clamp to the first available
+        // index and report it as the truncation marker.
+        assert_eq!(clamp_fetch_low(3, 8), Some(8));
+    }
+
+    #[test]
+    fn test_fetch_request_records_truncation_and_clamped_range() {
+        // A follower asks for entries starting below what's been
+        // compacted; the worker serving the request clamps `low` and
+        // records where it truncated to, instead of erroring out.
+        let mut request = req(1, 3, 20, 100);
+        let first_index = 8;
+        request.truncated_to = clamp_fetch_low(request.low, first_index);
+        if let Some(truncated_to) = request.truncated_to {
+            request.low = truncated_to;
+        }
+
+        assert_eq!(request.low, 8);
+        assert_eq!(request.truncated_to, Some(8));
+    }
+
+    #[test]
+    fn test_large_fetch_does_not_starve_small_fetches() {
+        let mut q = FetchFairnessQueue::new(1024);
+        // A lagging peer submits one huge fetch.
+        q.push(req(1, 0, 1_000_000, 1_000_000));
+        // Several other peers submit small catch-up fetches.
+        q.push(req(2, 0, 10, 100));
+        q.push(req(3, 0, 10, 100));
+
+        let batch = q.next_turn();
+        assert_eq!(batch.len(), 3);
+        // Every peer, including the small ones, is served in the same turn
+        // as the large fetch instead of waiting behind it.
+        let peers: Vec<u64> = batch.iter().map(|r| r.to_peer).collect();
+        assert!(peers.contains(&1));
+        assert!(peers.contains(&2));
+        assert!(peers.contains(&3));
+
+        // The small fetches complete in one turn and drop out of rotation.
+        assert!(!q.is_empty());
+        let batch2 = q.next_turn();
+        assert_eq!(batch2.len(), 1);
+        assert_eq!(batch2[0].to_peer, 1);
+    }
+
+    #[test]
+    fn test_large_fetch_is_capped_per_turn() {
+        let mut q = FetchFairnessQueue::new(1024);
+        q.push(req(1, 0, 1_000_000, 1_000_000));
+        let batch = q.next_turn();
+        assert_eq!(batch[0].remaining_bytes, 1024);
+        assert!(!q.is_empty());
+    }
+
+    #[test]
+    fn test_cancel_region_drops_only_pending_requests_for_that_region() {
+        let mut q = FetchFairnessQueue::new(1024);
+        q.push(FetchRequest {
+            region_id: 1,
+            to_peer: 1,
+            low: 0,
+            high: 10,
+            remaining_bytes: 100,
+            truncated_to: None,
+        });
+        q.push(FetchRequest {
+            region_id: 2,
+            to_peer: 1,
+            low: 0,
+            high: 10,
+            remaining_bytes: 100,
+            truncated_to: None,
+        });
+        q.push(FetchRequest {
+            region_id: 2,
+            to_peer: 2,
+            low: 0,
+            high: 10,
+            remaining_bytes: 100,
+            truncated_to: None,
+        });
+
+        assert_eq!(q.cancel_region(1), 1);
+        assert!(!q.is_empty());
+
+        let batch = q.next_turn();
+        // Only region 2's requests remain, for both peers.
+        assert!(batch.iter().all(|r| r.region_id == 2));
+        assert_eq!(batch.len(), 2);
+    }
+
+    #[test]
+    fn test_cancel_region_drains_a_peer_with_no_remaining_requests() {
+        let mut q = FetchFairnessQueue::new(1024);
+        q.push(req(1, 0, 10, 100));
+
+        assert_eq!(q.cancel_region(1), 1);
+        assert!(q.is_empty());
+        assert_eq!(q.next_turn().len(), 0);
+    }
+
+    #[test]
+    fn test_in_flight_fetches_suppresses_result_for_cancelled_region() {
+        let mut in_flight = InFlightFetches::default();
+        assert!(!in_flight.is_cancelled(1));
+
+        in_flight.cancel_region(1);
+        assert!(in_flight.is_cancelled(1));
+        assert!(!in_flight.is_cancelled(2));
+
+        // Once the region is reused, a stale cancellation must not linger.
+        in_flight.clear(1);
+        assert!(!in_flight.is_cancelled(1));
+    }
+
+    #[test]
+    fn test_byte_budget_bounds_total_in_flight_bytes() {
+        let budget = FetchByteBudget::new(1024);
+
+        // Several large fetches try to reserve buffers well beyond the
+        // budget; only as many as fit are admitted at any point in time.
+        let mut admitted = Vec::new();
+        for _ in 0..8 {
+            if budget.try_reserve(300) {
+                admitted.push(300u64);
+            }
+        }
+        assert!(budget.used() <= 1024);
+        assert_eq!(budget.used(), admitted.iter().sum::<u64>());
+        // The budget is exhausted, so a further fetch is rejected rather
+        // than pushing usage over the limit.
+        assert!(!budget.try_reserve(300));
+
+        // Releasing frees room for a later fetch to be admitted again.
+        let freed = admitted.pop().unwrap();
+        budget.release(freed);
+        assert!(budget.try_reserve(300));
+        assert!(budget.used() <= 1024);
+    }
+}
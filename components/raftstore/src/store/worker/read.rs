@@ -4,6 +4,7 @@ use std::cell::Cell;
 use std::fmt::{self, Display, Formatter};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use std::thread;
 use std::time::Duration;
 
 use crossbeam::atomic::AtomicCell;
@@ -29,7 +30,7 @@ use engine_traits::{KvEngine, RaftEngine};
 use tikv_util::lru::LruCache;
 use tikv_util::time::monotonic_raw_now;
 use tikv_util::time::{Instant, ThreadReadId};
-use tikv_util::{debug, error};
+use tikv_util::{debug, error, thd_name};
 
 use super::metrics::*;
 use crate::store::fsm::store::StoreMeta;
@@ -212,7 +213,7 @@ impl ReadDelegate {
             region: Arc::new(region),
             peer_id,
             term: peer.term(),
-            applied_index_term: peer.get_store().applied_index_term(),
+            applied_index_term: peer.applied_index_term_or(u64::MAX),
             leader_lease: None,
             last_valid_ts: Timespec::new(0, 0),
             tag: format!("[region {}] {}", region_id, peer_id),
@@ -306,6 +307,17 @@ impl Progress {
     }
 }
 
+/// Outcome of attempting to serve a read from local state, used by
+/// `LocalReader::try_local_read`.
+enum LocalReadResult<S: engine_traits::Snapshot> {
+    /// A response is ready to hand to the caller's callback, whether that's
+    /// a successful local read or a hard error (e.g. store id mismatch).
+    Handled(ReadResponse<S>),
+    /// Not serviceable locally right now; the caller should forward to
+    /// raftstore.
+    Redirect,
+}
+
 pub struct LocalReader<C, E>
 where
     C: ProposalRouter<E::Snapshot>,
@@ -506,11 +518,27 @@ where
 
     pub fn propose_raft_command(
         &mut self,
-        mut read_id: Option<ThreadReadId>,
+        read_id: Option<ThreadReadId>,
         req: RaftCmdRequest,
         cb: Callback<E::Snapshot>,
     ) {
-        match self.pre_propose_raft_command(&req) {
+        match self.try_local_read(read_id, &req) {
+            LocalReadResult::Handled(response) => cb.invoke_read(response),
+            LocalReadResult::Redirect => self.redirect(RaftCommand::new(req, cb)),
+        }
+    }
+
+    /// Attempts to serve `req` from this store's local state, without
+    /// forwarding to raftstore. Returns `Redirect` for exactly the cases
+    /// `propose_raft_command` used to hand off to `self.redirect`: no local
+    /// delegate, a stale epoch, or (most commonly) the leader lease not
+    /// covering `read_id`'s snapshot timestamp.
+    fn try_local_read(
+        &mut self,
+        mut read_id: Option<ThreadReadId>,
+        req: &RaftCmdRequest,
+    ) -> LocalReadResult<E::Snapshot> {
+        match self.pre_propose_raft_command(req) {
             Ok(Some(delegate)) => {
                 let snapshot_ts = match read_id.as_mut() {
                     // If this peer became Leader not long ago and just after the cached
@@ -526,34 +554,82 @@ where
                 // Leader can read local if and only if it is in lease.
                 if delegate.is_in_leader_lease(snapshot_ts, &mut self.metrics) {
                     // Cache snapshot_time for remaining requests in the same batch.
-                    let mut response = self.execute(&req, &delegate.region, None, read_id);
+                    let mut response = self.execute(req, &delegate.region, None, read_id);
                     cmd_resp::bind_term(&mut response.response, delegate.term);
                     if let Some(snap) = response.snapshot.as_mut() {
                         snap.max_ts_sync_status = Some(delegate.max_ts_sync_status.clone());
                     }
                     response.txn_extra_op = delegate.txn_extra_op.load();
-                    cb.invoke_read(response);
+                    LocalReadResult::Handled(response)
                 } else {
-                    // Forward to raftstore.
-                    self.redirect(RaftCommand::new(req, cb));
+                    LocalReadResult::Redirect
                 }
             }
-            // Forward to raftstore.
-            Ok(None) => self.redirect(RaftCommand::new(req, cb)),
+            Ok(None) => LocalReadResult::Redirect,
             Err(e) => {
                 let mut response = cmd_resp::new_error(e);
                 if let Some(ref delegate) = self.delegates.get(&req.get_header().get_region_id()) {
                     cmd_resp::bind_term(&mut response, delegate.term);
                 }
-                cb.invoke_read(ReadResponse {
+                LocalReadResult::Handled(ReadResponse {
                     response,
                     snapshot: None,
                     txn_extra_op: TxnExtraOp::Noop,
-                });
+                })
             }
         }
     }
 
+    /// Like `read`, but re-checks the local read path up to `attempts` times
+    /// before forwarding to raftstore. This is meant for a lease that just
+    /// expired and is expected to renew imminently from a heartbeat that's
+    /// already in flight: each re-check re-reads the delegate's current
+    /// lease state, waiting `RETRY_BACKOFF` between checks, and never
+    /// relaxes the lease check itself, so a read that isn't safe to serve
+    /// locally is still never served locally -- retrying only gives that
+    /// check more chances to pass before paying for a read-index round trip.
+    ///
+    /// `LocalReadRouter` is a plain synchronous trait called from whatever
+    /// thread happens to own the read (readpool workers, gRPC threads,
+    /// tests, ...), so there's no async runtime this method can assume is
+    /// available to await a delay on without risking a panic outside a
+    /// compatible executor. Once the first re-check comes back as
+    /// `Redirect`, the remaining wait-and-retry is handed off to a
+    /// dedicated, short-lived thread instead of blocking the caller with
+    /// `thread::sleep`.
+    pub fn read_with_retry(
+        &mut self,
+        read_id: Option<ThreadReadId>,
+        req: RaftCmdRequest,
+        cb: Callback<E::Snapshot>,
+        attempts: u32,
+    ) where
+        C: Clone + Send + 'static,
+    {
+        match self.try_local_read(read_id.clone(), &req) {
+            LocalReadResult::Handled(response) => {
+                cb.invoke_read(response);
+                self.metrics.maybe_flush();
+                return;
+            }
+            LocalReadResult::Redirect => {}
+        }
+
+        if attempts <= 1 {
+            self.read(read_id, req, cb);
+            return;
+        }
+
+        let mut reader = self.clone();
+        thread::Builder::new()
+            .name(thd_name!("local-read-retry"))
+            .spawn(move || {
+                thread::sleep(RETRY_BACKOFF);
+                reader.read_with_retry(read_id, req, cb, attempts - 1);
+            })
+            .unwrap();
+    }
+
     /// If read requests are received at the same RPC request, we can create one snapshot for all
     /// of them and check whether the time when the snapshot was created is in lease. We use
     /// ThreadReadId to figure out whether this RaftCommand comes from the same RPC request with
@@ -632,6 +708,9 @@ impl<'r, 'm> RequestInspector for Inspector<'r, 'm> {
 
 const METRICS_FLUSH_INTERVAL: u64 = 15_000; // 15s
 
+/// Delay between local-read re-checks in `LocalReader::read_with_retry`.
+const RETRY_BACKOFF: Duration = Duration::from_millis(2);
+
 #[derive(Clone)]
 struct ReadMetrics {
     local_executed_requests: i64,
@@ -1067,6 +1146,97 @@ mod tests {
         assert!(reader.get_delegate(1).is_none());
     }
 
+    #[test]
+    fn test_read_with_retry_catches_lease_renewal() {
+        let store_id = 2;
+        let store_meta = Arc::new(Mutex::new(StoreMeta::new(0)));
+        let (_tmp, mut reader, rx) = new_reader(
+            "test-local-reader-retry",
+            store_id,
+            store_meta.clone(),
+        );
+
+        let mut region1 = metapb::Region::default();
+        region1.set_id(1);
+        let prs = new_peers(store_id, vec![2, 3, 4]);
+        region1.set_peers(prs.clone().into());
+        let epoch13 = {
+            let mut ep = metapb::RegionEpoch::default();
+            ep.set_conf_ver(1);
+            ep.set_version(3);
+            ep
+        };
+        let leader2 = prs[0].clone();
+        region1.set_region_epoch(epoch13.clone());
+        let term6 = 6;
+
+        let mut cmd = RaftCmdRequest::default();
+        let mut header = RaftRequestHeader::default();
+        header.set_region_id(1);
+        header.set_peer(leader2.clone());
+        header.set_region_epoch(epoch13);
+        header.set_term(term6);
+        cmd.set_header(header);
+        let mut req = Request::default();
+        req.set_cmd_type(CmdType::Snap);
+        cmd.set_requests(vec![req].into());
+
+        // A short-lived lease that's already expired by the time the read
+        // comes in: this is the "reader stub that fails once" case -- the
+        // very first local-read check must see it as expired.
+        let mut lease = Lease::new(Duration::milliseconds(1));
+        lease.renew(monotonic_raw_now());
+        let expired_remote = lease.maybe_new_remote_lease(term6).unwrap();
+        {
+            let mut meta = store_meta.lock().unwrap();
+            let read_delegate = ReadDelegate {
+                tag: String::new(),
+                region: Arc::new(region1),
+                peer_id: leader2.get_id(),
+                term: term6,
+                applied_index_term: term6,
+                leader_lease: Some(expired_remote),
+                last_valid_ts: Timespec::new(0, 0),
+                txn_extra_op: Arc::new(AtomicCell::new(TxnExtraOp::default())),
+                max_ts_sync_status: Arc::new(AtomicU64::new(0)),
+                track_ver: TrackVer::new(),
+            };
+            meta.readers.insert(1, read_delegate);
+        }
+        thread::sleep(std::time::Duration::from_millis(5));
+
+        // Simulate a heartbeat renewing the lease shortly after the first
+        // failed check, well within `read_with_retry`'s retry window.
+        let mut lease = Lease::new(Duration::seconds(5));
+        lease.renew(monotonic_raw_now());
+        let renewed_remote = lease.maybe_new_remote_lease(term6).unwrap();
+        let store_meta_clone = store_meta.clone();
+        thread::spawn(move || {
+            thread::sleep(std::time::Duration::from_millis(6));
+            let mut meta = store_meta_clone.lock().unwrap();
+            meta.readers
+                .get_mut(&1)
+                .unwrap()
+                .update(Progress::leader_lease(renewed_remote));
+        });
+
+        let (tx, done_rx) = channel();
+        reader.read_with_retry(
+            None,
+            cmd,
+            Callback::Read(Box::new(move |resp: ReadResponse<KvTestSnapshot>| {
+                tx.send(resp.snapshot.is_some()).unwrap();
+            })),
+            10,
+        );
+
+        // The renewed lease should have been picked up by a retry, so the
+        // read was served locally: the callback saw a snapshot, and nothing
+        // was forwarded to raftstore.
+        assert!(done_rx.recv_timeout(Duration::seconds(1).to_std().unwrap()).unwrap());
+        assert_eq!(rx.try_recv().unwrap_err(), TryRecvError::Empty);
+    }
+
     #[test]
     fn test_read_delegate_cache_update() {
         let store_id = 2;
@@ -0,0 +1,27 @@
+// Copyright 2022 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Resource control keeps track of configured resource groups and enforces
+//! fair use of cluster resources (currently CPU and IO cost) between them.
+//!
+//! Fairness is implemented with a virtual-time based scheduling algorithm,
+//! similar in spirit to weighted fair queuing: every group accumulates a
+//! virtual time proportional to the resource it consumes divided by its
+//! weight (derived from its configured RU quota), and tasks belonging to
+//! the group with the smallest virtual time are scheduled first.
+
+#[macro_use]
+extern crate lazy_static;
+
+mod metrics;
+mod resource_group;
+
+pub use resource_group::{
+    command_priority_to_level, CommandPriority, GroupHandle, GroupLimitPolicy, GroupMode,
+    GroupStats, GroupSummary, ManagerEvent, ManagerEventKind, ResourceConsumeType,
+    ResourceControlContext, ResourceController, ResourceGroup, ResourceGroupError,
+    ResourceGroupManager, UnknownGroupModePolicy, ZeroQuotaPolicy,
+};
+
+/// The name of the resource group that requests are attributed to when no
+/// group is explicitly specified.
+pub const DEFAULT_RESOURCE_GROUP_NAME: &str = "default";
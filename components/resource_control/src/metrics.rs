@@ -0,0 +1,76 @@
+// Copyright 2022 TiKV Project Authors. Licensed under Apache-2.0.
+
+use prometheus::*;
+
+lazy_static! {
+    pub static ref RESOURCE_GROUP_VT_GROWTH_RATE_VEC: GaugeVec = register_gauge_vec!(
+        "tikv_resource_group_vt_growth_rate",
+        "Virtual-time growth rate of a resource group between update_min_virtual_time cycles",
+        &["name"]
+    )
+    .unwrap();
+
+    pub static ref RESOURCE_GROUP_RUNAWAY_TOTAL_VEC: IntCounterVec = register_int_counter_vec!(
+        "tikv_resource_group_runaway_total",
+        "Number of times a resource group's virtual-time growth rate was flagged as runaway",
+        &["name"]
+    )
+    .unwrap();
+
+    pub static ref RESOURCE_CONTROLLER_LAST_MIN_VT_VEC: IntGaugeVec = register_int_gauge_vec!(
+        "tikv_resource_controller_last_min_vt",
+        "The virtual time a controller's newly added groups are currently seeded with",
+        &["name"]
+    )
+    .unwrap();
+
+    // Populated by `ResourceController::flush_virtual_time_metrics`, on the
+    // same "call it periodically from a metrics-flush loop" convention as
+    // `file_system`'s `flush_io_latency_metrics`, rather than through any
+    // kind of registered trace-provider mechanism.
+    pub static ref RESOURCE_GROUP_VIRTUAL_TIME_VEC: IntGaugeVec = register_int_gauge_vec!(
+        "tikv_resource_group_virtual_time",
+        "Current virtual time of a resource group",
+        &["name"]
+    )
+    .unwrap();
+
+    pub static ref RESOURCE_GROUP_WEIGHT_VEC: IntGaugeVec = register_int_gauge_vec!(
+        "tikv_resource_group_weight",
+        "Current scheduling weight of a resource group, i.e. max(ru_quota, 1)",
+        &["name"]
+    )
+    .unwrap();
+
+    pub static ref RESOURCE_GROUP_ADVANCE_MIN_VT_DURATION_SECONDS: HistogramVec = register_histogram_vec!(
+        "tikv_resource_group_advance_min_vt_duration_seconds",
+        "Time spent in one ResourceController::update_min_virtual_time call, per controller",
+        &["name"]
+    )
+    .unwrap();
+
+    pub static ref RESOURCE_CONTROLLER_DEFAULT_FALLBACK_VEC: IntGaugeVec = register_int_gauge_vec!(
+        "tikv_resource_controller_default_fallback_total",
+        "Number of times ResourceController::priority_of fell back to the default resource group because the requested group was not registered",
+        &["name"]
+    )
+    .unwrap();
+
+    // Only populated under the `lock-contention-metrics` feature; see
+    // `ResourceController::read_trackers`.
+    #[cfg(feature = "lock-contention-metrics")]
+    pub static ref RESOURCE_CONTROLLER_TRACKERS_LOCK_READS_VEC: IntCounterVec = register_int_counter_vec!(
+        "tikv_resource_controller_trackers_lock_reads_total",
+        "Number of times a controller has acquired its trackers lock for reading",
+        &["name"]
+    )
+    .unwrap();
+
+    #[cfg(feature = "lock-contention-metrics")]
+    pub static ref RESOURCE_CONTROLLER_TRACKERS_LOCK_WAIT_NANOS_VEC: IntCounterVec = register_int_counter_vec!(
+        "tikv_resource_controller_trackers_lock_wait_nanos_total",
+        "Cumulative time, in nanoseconds, a controller has spent waiting to acquire its trackers lock for reading",
+        &["name"]
+    )
+    .unwrap();
+}
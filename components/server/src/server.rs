@@ -57,6 +57,7 @@ use raftstore::{
         SplitCheckRunner, SplitConfigManager, StoreMsg,
     },
 };
+use resource_control::{GroupMode, ResourceGroup, ResourceGroupManager};
 use security::SecurityManager;
 use tikv::{
     config::{ConfigController, DBConfigManger, DBType, TiKvConfig, DEFAULT_ROCKSDB_SUB_DIR},
@@ -163,6 +164,7 @@ struct TiKVServer<ER: RaftEngine> {
     concurrency_manager: ConcurrencyManager,
     env: Arc<Environment>,
     background_worker: Worker,
+    resource_manager: Arc<ResourceGroupManager>,
 }
 
 struct TiKVEngines<ER: RaftEngine> {
@@ -223,6 +225,19 @@ impl<ER: RaftEngine> TiKVServer<ER> {
         let latest_ts = block_on(pd_client.get_tso()).expect("failed to get timestamp from PD");
         let concurrency_manager = ConcurrencyManager::new(latest_ts);
 
+        // Every command is attributed to `DEFAULT_RESOURCE_GROUP_NAME` until
+        // requests carry a real resource-group tag; register it up front so
+        // `TxnScheduler`'s virtual-time and admission-control bookkeeping
+        // (see `create_raft_storage`) has a group to actually track from the
+        // first command onward, instead of silently no-oping until some
+        // other group gets added later.
+        let resource_manager = Arc::new(ResourceGroupManager::default());
+        resource_manager.add_resource_group(ResourceGroup {
+            name: resource_control::DEFAULT_RESOURCE_GROUP_NAME.to_string(),
+            mode: GroupMode::RuMode,
+            ru_quota: 10_000,
+        });
+
         TiKVServer {
             config,
             cfg_controller: Some(cfg_controller),
@@ -243,6 +258,7 @@ impl<ER: RaftEngine> TiKVServer<ER> {
             concurrency_manager,
             env,
             background_worker,
+            resource_manager,
         }
     }
 
@@ -564,6 +580,7 @@ impl<ER: RaftEngine> TiKVServer<ER> {
             lock_mgr.clone(),
             self.concurrency_manager.clone(),
             lock_mgr.get_pipelined(),
+            Some(self.resource_manager.clone()),
         )
         .unwrap_or_else(|e| fatal!("failed to create raft storage: {}", e));
 
@@ -1185,6 +1185,42 @@ impl<T: Simulator> Cluster<T> {
         }
     }
 
+    /// Promotes `learner` to a voter and transfers leadership to it,
+    /// proposing both admin commands back to back on the current leader
+    /// instead of going through separate PD-scheduled operators.
+    ///
+    /// Raft doesn't support folding a conf change and a leader transfer into
+    /// a single log entry -- a leader transfer isn't even replicated through
+    /// the raft log the same way a conf change is -- so this isn't a single
+    /// atomic operation. What it does remove is the round trip through PD's
+    /// operator scheduler for each step, which is what leaves a region
+    /// leaderless for an avoidable stretch during recovery: the promote is
+    /// proposed and, once it succeeds, the transfer follows immediately from
+    /// the same call.
+    pub fn promote_learner_and_transfer_leader(
+        &mut self,
+        region_id: u64,
+        learner: metapb::Peer,
+    ) -> Result<RaftCmdResponse> {
+        let mut voter = learner.clone();
+        voter.set_role(metapb::PeerRole::Voter);
+
+        let epoch = self.get_region_epoch(region_id);
+        let promote = new_admin_request(
+            region_id,
+            &epoch,
+            new_change_peer_request(ConfChangeType::AddNode, voter.clone()),
+        );
+        let promote_resp = self.call_command_on_leader(promote, Duration::from_secs(5))?;
+        if promote_resp.get_header().has_error() {
+            return Ok(promote_resp);
+        }
+
+        let epoch = self.get_region_epoch(region_id);
+        let transfer = new_admin_request(region_id, &epoch, new_transfer_leader_cmd(voter));
+        self.call_command_on_leader(transfer, Duration::from_secs(5))
+    }
+
     pub fn get_snap_dir(&self, node_id: u64) -> String {
         self.sim.rl().get_snap_dir(node_id)
     }
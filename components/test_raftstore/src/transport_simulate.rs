@@ -239,7 +239,9 @@ impl<C: RaftStoreRouter<RocksEngine>> RaftStoreRouter<RocksEngine> for SimulateT
         self.ch.significant_send(region_id, msg)
     }
 
-    fn broadcast_normal(&self, _: impl FnMut() -> PeerMsg<RocksEngine>) {}
+    fn broadcast_normal(&self, msg_gen: impl FnMut() -> PeerMsg<RocksEngine>) {
+        self.ch.broadcast_normal(msg_gen)
+    }
 }
 
 impl<C: LocalReadRouter<RocksEngine>> LocalReadRouter<RocksEngine> for SimulateTransport<C> {
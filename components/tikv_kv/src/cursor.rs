@@ -389,6 +389,11 @@ impl<I: Iterator> Cursor<I> {
         }
     }
 
+    /// See `Iterator::take_io_skip_hint`.
+    pub fn take_io_skip_hint(&mut self) -> usize {
+        self.iter.take_io_skip_hint()
+    }
+
     #[inline(never)]
     fn handle_error_status(&self, e: Error) -> Result<()> {
         // Split out the error case to reduce hot-path code size.
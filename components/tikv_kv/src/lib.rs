@@ -282,6 +282,18 @@ pub trait Iterator: Send {
         Ok(())
     }
 
+    /// Returns and resets a count of internal work (e.g. entries skipped
+    /// while searching for the next visible one) this iterator has done
+    /// since the last call. Iterators that never skip internally can rely
+    /// on the default of always reporting 0. Callers that drive a scan in
+    /// bounded time slices (see `raw::store`'s cooperative yielding) can
+    /// fold this into their own work counters so an iterator that skips a
+    /// lot internally without ever advancing the caller's cursor still
+    /// yields on schedule.
+    fn take_io_skip_hint(&mut self) -> usize {
+        0
+    }
+
     /// Only be called when `self.valid() == Ok(true)`.
     fn key(&self) -> &[u8];
     /// Only be called when `self.valid() == Ok(true)`.
@@ -161,6 +161,28 @@ impl<T> Receiver<T> {
     pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
         self.receiver.recv_timeout(timeout)
     }
+
+    /// Pulls up to `max` already-queued messages in one call, so a poller
+    /// draining a burst pays one round of bookkeeping instead of one
+    /// `try_recv` per message.
+    ///
+    /// This lives on `Receiver` rather than on `BasicMailbox`, which is the
+    /// more common place to look for mailbox operations: the receiving end
+    /// of a mailbox's channel is owned directly by the concrete `Fsm`
+    /// implementation (see `batch_system::test_runner::Handler::handle`,
+    /// which drains `Runner`'s `recv` field this way), not by `BasicMailbox`
+    /// itself, so this is what an `Fsm`'s own poll loop calls to drain its
+    /// mailbox.
+    pub fn try_recv_batch(&self, max: usize) -> Vec<T> {
+        let mut batch = Vec::with_capacity(std::cmp::min(max, self.len()));
+        while batch.len() < max {
+            match self.try_recv() {
+                Ok(msg) => batch.push(msg),
+                Err(_) => break,
+            }
+        }
+        batch
+    }
 }
 
 impl<T> Drop for Receiver<T> {
@@ -222,6 +244,14 @@ impl<T> LooseBoundedSender<T> {
         self.sender.is_empty()
     }
 
+    /// Returns true if the channel length is at or over its (loose) capacity
+    /// limit. `try_send` checks the limit only every `CHECK_INTERVAL`
+    /// attempts, so it may still accept a message while this returns true.
+    #[inline]
+    pub fn is_busy(&self) -> bool {
+        self.len() >= self.limit
+    }
+
     /// Send a message regardless its capacity limit.
     #[inline]
     pub fn force_send(&self, t: T) -> Result<(), SendError<T>> {
@@ -489,4 +519,23 @@ mod tests {
         let elapsed = timer.elapsed();
         assert!(elapsed >= Duration::from_millis(100), "{:?}", elapsed);
     }
+
+    #[test]
+    fn test_try_recv_batch() {
+        let (tx, rx) = unbounded();
+        for i in 0..5 {
+            tx.send(i).unwrap();
+        }
+
+        let batch = rx.try_recv_batch(3);
+        assert_eq!(batch, vec![0, 1, 2]);
+
+        // Fewer messages than `max` are available: only what's queued comes
+        // back, in order.
+        let batch = rx.try_recv_batch(10);
+        assert_eq!(batch, vec![3, 4]);
+
+        // Nothing left to receive.
+        assert!(rx.try_recv_batch(10).is_empty());
+    }
 }
@@ -73,6 +73,23 @@ impl FuturePool {
         self.env.metrics_running_task_count.get() as usize
     }
 
+    /// Gets the total number of tasks that have run to completion so far.
+    /// Monotonically increasing for the lifetime of the pool, unlike
+    /// `get_running_task_count` which drops back down as tasks finish.
+    #[inline]
+    pub fn get_handled_task_count(&self) -> u64 {
+        self.env.metrics_handled_task_count.get() as u64
+    }
+
+    /// Returns whether a `spawn` right now would succeed instead of
+    /// returning `Full`. Racy against concurrent spawns/completions, so this
+    /// is meant for a backpressure loop that re-checks before actually
+    /// spawning, not as a reservation.
+    #[inline]
+    pub fn has_capacity(&self) -> bool {
+        self.gate_spawn().is_ok()
+    }
+
     fn gate_spawn(&self) -> Result<(), Full> {
         fail_point!("future_pool_spawn_full", |_| Err(Full {
             current_tasks: 100,
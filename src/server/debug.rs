@@ -28,6 +28,7 @@ use collections::HashSet;
 use engine_rocks::RocksMvccProperties;
 use raftstore::coprocessor::get_region_approximate_middle;
 use raftstore::store::util as raftstore_util;
+use raftstore::store::util::RegionInconsistency;
 use raftstore::store::PeerStorage;
 use raftstore::store::{write_initial_apply_state, write_initial_raft_state, write_peer_state};
 use tikv_util::codec::bytes;
@@ -477,6 +478,7 @@ impl<ER: RaftEngine> Debugger<ER> {
 
     pub fn bad_regions(&self) -> Result<Vec<(u64, Error)>> {
         let mut res = Vec::new();
+        let mut live_regions = Vec::new();
 
         let from = keys::REGION_META_MIN_KEY.to_owned();
         let to = keys::REGION_META_MAX_KEY.to_owned();
@@ -491,12 +493,12 @@ impl<ER: RaftEngine> Debugger<ER> {
         let fake_worker = Worker::new("fake-snap-worker");
         let fake_snap_worker = fake_worker.lazy_build("fake-snap");
 
-        let check_value = |value: &[u8]| -> Result<()> {
+        let check_value = |value: &[u8]| -> Result<Option<Region>> {
             let mut local_state = RegionLocalState::default();
             box_try!(local_state.merge_from_bytes(&value));
 
             match local_state.get_state() {
-                PeerState::Tombstone | PeerState::Applying => return Ok(()),
+                PeerState::Tombstone | PeerState::Applying => return Ok(None),
                 _ => {}
             }
 
@@ -534,7 +536,7 @@ impl<ER: RaftEngine> Debugger<ER> {
                 peer_storage,
                 &slog_global::get_global()
             ));
-            Ok(())
+            Ok(Some(region.clone()))
         };
 
         while box_try!(iter.valid()) {
@@ -544,12 +546,32 @@ impl<ER: RaftEngine> Debugger<ER> {
                     box_try!(iter.next());
                     continue;
                 }
-                if let Err(e) = check_value(value) {
-                    res.push((region_id, e));
+                match check_value(value) {
+                    Ok(Some(region)) => live_regions.push(region),
+                    Ok(None) => {}
+                    Err(e) => res.push((region_id, e)),
                 }
             }
             box_try!(iter.next());
         }
+
+        // Beyond "can this region's own raft state even boot", also check the
+        // low-level invariants across every live region on the store: no
+        // shared ids, no overlapping ranges, no zero epoch version. These
+        // don't surface as a `RawNode::new` failure above, only much later as
+        // a confusing `check_key_in_region` error somewhere else.
+        live_regions.sort_by(|a, b| a.get_start_key().cmp(b.get_start_key()));
+        if let Err(problems) = raftstore_util::verify_region_consistency(&live_regions) {
+            for problem in problems {
+                let region_id = match problem {
+                    RegionInconsistency::DuplicateRegionId(id) => id,
+                    RegionInconsistency::OverlappingRegions { first, .. } => first,
+                    RegionInconsistency::ZeroEpoch(id) => id,
+                };
+                res.push((region_id, Error::Other(format!("{:?}", problem).into())));
+            }
+        }
+
         Ok(res)
     }
 
@@ -700,6 +722,29 @@ impl<ER: RaftEngine> Debugger<ER> {
         Ok(())
     }
 
+    /// Bulk version of [`recreate_region`](Self::recreate_region): writes
+    /// many regions at once via [`bootstrap_many_regions`], instead of one
+    /// `recreate_region` call per region.
+    ///
+    /// Unlike `recreate_region`, this does not scan the store's existing
+    /// regions for overlap with each incoming one -- doing that scan once
+    /// per region is exactly the per-region cost bulk recreation exists to
+    /// avoid. It relies instead on the caller having already allocated a
+    /// fresh id for every region from PD (so none can collide with an
+    /// existing region by id) and on `verify_region_consistency` to catch
+    /// overlaps within the batch itself. It is still the caller's
+    /// responsibility to make sure none of `regions`' key ranges overlap a
+    /// region the store already holds -- this is meant for recreating
+    /// many regions onto a store that doesn't have conflicting ones yet,
+    /// e.g. one just rebuilt from an empty data directory.
+    pub fn recreate_regions(&self, mut regions: Vec<Region>, batch_size: usize) -> Result<()> {
+        regions.sort_by(|a, b| a.get_start_key().cmp(b.get_start_key()));
+        if let Err(problems) = raftstore_util::verify_region_consistency(&regions) {
+            return Err(Error::Other(format!("{:?}", problems).into()));
+        }
+        raftstore::store::bootstrap_many_regions(&self.engines, &regions, batch_size)
+    }
+
     pub fn get_store_id(&self) -> Result<u64> {
         let db = &self.engines.kv;
         db.get_msg::<StoreIdent>(keys::STORE_IDENT_KEY)
@@ -25,6 +25,7 @@ use raftstore::store::fsm::{ApplyRouter, RaftBatchSystem, RaftRouter};
 use raftstore::store::AutoSplitController;
 use raftstore::store::{self, initial_region, Config as StoreConfig, SnapManager, Transport};
 use raftstore::store::{GlobalReplicationState, PdTask, SplitCheckTask};
+use resource_control::ResourceGroupManager;
 use tikv_util::config::VersionTrack;
 use tikv_util::worker::{FutureWorker, Scheduler, Worker};
 
@@ -40,6 +41,7 @@ pub fn create_raft_storage<S>(
     lock_mgr: LockManager,
     concurrency_manager: ConcurrencyManager,
     pipelined_pessimistic_lock: Arc<AtomicBool>,
+    resource_manager: Option<Arc<ResourceGroupManager>>,
 ) -> Result<Storage<RaftKv<S>, LockManager>>
 where
     S: RaftStoreRouter<RocksEngine> + LocalReadRouter<RocksEngine> + 'static,
@@ -51,6 +53,7 @@ where
         lock_mgr,
         concurrency_manager,
         pipelined_pessimistic_lock,
+        resource_manager,
     )?;
     Ok(store)
 }
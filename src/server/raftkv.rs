@@ -466,7 +466,12 @@ where
     }
 
     fn release_snapshot(&self) {
-        self.router.release_snapshot_cache();
+        // Best-effort: the cache being released is an optimization, not a
+        // correctness requirement, so it's fine to skip this round if the
+        // local reader is already borrowed elsewhere on this thread (e.g.
+        // reentrantly, from within the very read callback whose snapshot is
+        // being dropped here) rather than panicking.
+        let _ = self.router.try_release_snapshot_cache();
     }
 
     fn get_mvcc_properties_cf(
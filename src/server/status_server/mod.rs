@@ -683,6 +683,41 @@ where
         }
     }
 
+    /// Dumps the IO rate limiter's per-`IOType` accumulated read/write bytes
+    /// as JSON, for diagnosing which kind of traffic (raft log, compaction,
+    /// import, ...) is driving disk IO on this store.
+    pub async fn dump_io_stats(_req: Request<Body>) -> hyper::Result<Response<Body>> {
+        let stats = match file_system::get_io_rate_limiter().map(|r| r.statistics()) {
+            Some(stats) => stats,
+            None => {
+                return Ok(StatusServer::err_response(
+                    StatusCode::NOT_FOUND,
+                    "io rate limiter statistics are not enabled",
+                ));
+            }
+        };
+
+        let body = match serde_json::to_vec(&stats.export()) {
+            Ok(body) => body,
+            Err(err) => {
+                return Ok(StatusServer::err_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("fails to json: {}", err),
+                ));
+            }
+        };
+        match Response::builder()
+            .header("content-type", "application/json")
+            .body(hyper::Body::from(body))
+        {
+            Ok(resp) => Ok(resp),
+            Err(err) => Ok(StatusServer::err_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("fails to build response: {}", err),
+            )),
+        }
+    }
+
     fn start_serve<I, C>(&mut self, builder: HyperBuilder<I>)
     where
         I: Accept<Conn = C, Error = std::io::Error> + Send + 'static,
@@ -759,6 +794,7 @@ where
                             (Method::GET, path) if path.starts_with("/region") => {
                                 Self::dump_region_meta(req, router).await
                             }
+                            (Method::GET, "/debug/io_stats") => Self::dump_io_stats(req).await,
                             (Method::PUT, path) if path.starts_with("/log-level") => {
                                 Self::change_log_level(req).await
                             }
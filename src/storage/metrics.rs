@@ -295,6 +295,21 @@ lazy_static! {
         "Total number of pending commands."
     )
     .unwrap();
+    // Populated by `SchedPool`'s idle-flush background thread from
+    // `SchedPool::pool_metrics`, on the same periodic self-reporting
+    // convention as that thread's existing `tls_flush` calls.
+    pub static ref SCHED_POOL_RUNNING_TASKS_GAUGE_VEC: IntGaugeVec = register_int_gauge_vec!(
+        "tikv_scheduler_pool_running_tasks",
+        "Number of tasks currently occupying a scheduler pool worker thread",
+        &["pool"]
+    )
+    .unwrap();
+    pub static ref SCHED_POOL_QUEUED_TASKS_GAUGE_VEC: IntGaugeVec = register_int_gauge_vec!(
+        "tikv_scheduler_pool_queued_tasks",
+        "Number of tasks spawned onto a scheduler pool but not yet running",
+        &["pool"]
+    )
+    .unwrap();
     pub static ref SCHED_HISTOGRAM_VEC: HistogramVec = register_histogram_vec!(
         "tikv_scheduler_command_duration_seconds",
         "Bucketed histogram of command execution",
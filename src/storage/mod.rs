@@ -83,6 +83,7 @@ use kvproto::kvrpcpb::{
 };
 use raftstore::store::util::build_key_range;
 use rand::prelude::*;
+use resource_control::ResourceGroupManager;
 use std::{
     borrow::Cow,
     iter,
@@ -199,6 +200,7 @@ impl<E: Engine, L: LockManager> Storage<E, L> {
         lock_mgr: L,
         concurrency_manager: ConcurrencyManager,
         pipelined_pessimistic_lock: Arc<atomic::AtomicBool>,
+        resource_manager: Option<Arc<ResourceGroupManager>>,
     ) -> Result<Self> {
         let sched = TxnScheduler::new(
             engine.clone(),
@@ -209,6 +211,7 @@ impl<E: Engine, L: LockManager> Storage<E, L> {
             config.scheduler_pending_write_threshold.0 as usize,
             pipelined_pessimistic_lock,
             config.enable_async_apply_prewrite,
+            resource_manager,
         );
 
         info!("Storage started.");
@@ -1776,6 +1779,7 @@ impl<E: Engine, L: LockManager> TestStorageBuilder<E, L> {
             self.lock_mgr,
             ConcurrencyManager::new(1.into()),
             self.pipelined_pessimistic_lock,
+            None,
         )
     }
 }
@@ -169,7 +169,11 @@ impl<'a, S: Snapshot> RawStoreInner<S> {
         let mut row_count = 0;
         let mut time_slice_start = Instant::now();
         while cursor.valid()? && pairs.len() < limit {
-            row_count += 1;
+            // A key can be skipped without ever showing up here, e.g. an
+            // expired TTL entry filtered out inside the iterator. Fold that
+            // work into `row_count` too, or a scan over a mostly-expired
+            // range would never trip the time slice check below.
+            row_count += 1 + cursor.take_io_skip_hint();
             if row_count >= MAX_BATCH_SIZE {
                 if time_slice_start.elapsed() > MAX_TIME_SLICE {
                     reschedule().await;
@@ -217,7 +221,9 @@ impl<'a, S: Snapshot> RawStoreInner<S> {
         let mut row_count = 0;
         let mut time_slice_start = Instant::now();
         while cursor.valid()? && pairs.len() < limit {
-            row_count += 1;
+            // See the comment in `forward_raw_scan`: fold skipped-internally
+            // work (e.g. expired TTL entries) into `row_count` as well.
+            row_count += 1 + cursor.take_io_skip_hint();
             if row_count >= MAX_BATCH_SIZE {
                 if time_slice_start.elapsed() > MAX_TIME_SLICE {
                     reschedule().await;
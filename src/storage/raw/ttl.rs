@@ -1,13 +1,31 @@
 // Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
 
-use crate::storage::kv::{Iterator, Result, Snapshot, TTL_TOMBSTONE};
+use crate::storage::kv::{ErrorInner as KvErrorInner, Iterator, Result, Snapshot, TTL_TOMBSTONE};
 use crate::storage::Statistics;
 
 use engine_traits::util::{get_expire_ts, strip_expire_ts, truncate_expire_ts};
 use engine_traits::CfName;
-use engine_traits::{IterOptions, ReadOptions};
+use engine_traits::{IterOptions, ReadOptions, CF_DEFAULT};
+use tikv_util::keybuilder::KeyBuilder;
 use txn_types::{Key, Value};
 
+/// Returns the smallest key that is greater than every key with `prefix` as
+/// a prefix, i.e. the exclusive upper bound of the prefix's key range.
+/// Returns `None` if `prefix` is empty or made up entirely of `0xff` bytes,
+/// in which case there is no finite upper bound.
+fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(&last) = end.last() {
+        if last == 0xff {
+            end.pop();
+        } else {
+            *end.last_mut().unwrap() += 1;
+            return Some(end);
+        }
+    }
+    None
+}
+
 #[cfg(test)]
 pub const TEST_CURRENT_TS: u64 = 100;
 
@@ -31,27 +49,189 @@ pub fn current_ts() -> u64 {
     TEST_CURRENT_TS
 }
 
+/// Encodes and decodes the `expire_ts` suffix a TTL-enabled value carries.
+/// Pulled out of `TTLSnapshot`/`TTLIterator` so an alternate value layout
+/// (e.g. a future versioned format) can be plugged in by implementing this
+/// trait, instead of forking either type. [`DefaultExpiryCodec`] is the
+/// historical fixed-width big-endian `u64` suffix from
+/// `engine_traits::util` and is what every existing caller gets by default.
+pub trait ExpiryCodec: Clone + Send + Sync + 'static {
+    /// Returns the `expire_ts` encoded in `value_with_ttl`, or `0` if the
+    /// value never expires.
+    fn get_expire_ts(&self, value_with_ttl: &[u8]) -> Result<u64>;
+
+    /// Returns `value_with_ttl` with the expiry encoding stripped off,
+    /// leaving only the caller-visible value.
+    fn strip_expire_ts<'a>(&self, value_with_ttl: &'a [u8]) -> &'a [u8];
+
+    /// Like `strip_expire_ts`, but truncates `value_with_ttl` in place.
+    fn truncate_expire_ts(&self, value_with_ttl: &mut Vec<u8>) -> Result<()>;
+}
+
+/// The historical TTL encoding: a big-endian `u64` `expire_ts` appended
+/// after the value.
+#[derive(Clone, Copy, Default)]
+pub struct DefaultExpiryCodec;
+
+impl ExpiryCodec for DefaultExpiryCodec {
+    fn get_expire_ts(&self, value_with_ttl: &[u8]) -> Result<u64> {
+        get_expire_ts(value_with_ttl)
+    }
+
+    fn strip_expire_ts<'a>(&self, value_with_ttl: &'a [u8]) -> &'a [u8] {
+        strip_expire_ts(value_with_ttl)
+    }
+
+    fn truncate_expire_ts(&self, value_with_ttl: &mut Vec<u8>) -> Result<()> {
+        truncate_expire_ts(value_with_ttl)
+    }
+}
+
 #[derive(Clone)]
-pub struct TTLSnapshot<S: Snapshot> {
+pub struct TTLSnapshot<S: Snapshot, C: ExpiryCodec = DefaultExpiryCodec> {
     s: S,
     current_ts: u64,
+    /// The CFs whose values carry a TTL suffix and need decoding. `None`
+    /// means every CF is TTL-enabled, matching the historical behavior of
+    /// `TTLSnapshot::from`.
+    ttl_cfs: Option<std::collections::HashSet<CfName>>,
+    /// CFs that are never TTL-interpreted regardless of `ttl_cfs`. Distinct
+    /// from "not in `ttl_cfs`": that still means "no TTL decoding" today,
+    /// but is only a default derived from the enabled-CF set, whereas this
+    /// is an explicit exemption a caller sets when a CF's values must never
+    /// be misread as carrying a TTL suffix, e.g. because they legitimately
+    /// end in bytes that would parse as a plausible `expire_ts`.
+    exempt_cfs: std::collections::HashSet<CfName>,
+    codec: C,
+    /// See `TTLIterator::max_skip`. `0` (the default) means unlimited.
+    max_skip: usize,
+}
+
+impl<S: Snapshot> TTLSnapshot<S, DefaultExpiryCodec> {
+    /// Builds a `TTLSnapshot` that only applies TTL decoding to `ttl_cfs`.
+    /// `get_cf`/`iter_cf` on any other CF return raw values unchanged,
+    /// sparing CFs that never carry TTL-encoded values (e.g. lock) the
+    /// decode cost.
+    pub fn with_ttl_cfs(s: S, ttl_cfs: impl IntoIterator<Item = CfName>) -> Self {
+        Self::with_codec(s, ttl_cfs, DefaultExpiryCodec)
+    }
 }
 
-impl<S: Snapshot> TTLSnapshot<S> {
+impl<S: Snapshot, C: ExpiryCodec> TTLSnapshot<S, C> {
+    /// Like `with_ttl_cfs`, but with an explicit [`ExpiryCodec`] instead of
+    /// the default encoding.
+    pub fn with_codec(s: S, ttl_cfs: impl IntoIterator<Item = CfName>, codec: C) -> Self {
+        TTLSnapshot {
+            s,
+            current_ts: current_ts(),
+            ttl_cfs: Some(ttl_cfs.into_iter().collect()),
+            exempt_cfs: std::collections::HashSet::default(),
+            codec,
+            max_skip: 0,
+        }
+    }
+
+    /// Marks `cfs` as never TTL-interpreted, regardless of `ttl_cfs`:
+    /// `get_cf`, `get_cf_opt`, `exists_cf`, `iter`, `iter_cf` and
+    /// `prefix_iter` on an exempt CF return the raw stored bytes and never
+    /// call `get_expire_ts` on them at all, so a CF whose values legitimately
+    /// end in bytes that would parse as a plausible `expire_ts` is never
+    /// misread as carrying one. The default-CF shortcuts `get`, `get_opt`,
+    /// `get_with_ttl` and `exists` do not consult the exemption and always
+    /// decode the TTL suffix.
+    pub fn with_exempt_cfs(mut self, cfs: impl IntoIterator<Item = CfName>) -> Self {
+        self.exempt_cfs = cfs.into_iter().collect();
+        self
+    }
+
+    /// Caps the number of consecutive expired keys an iterator built from
+    /// this snapshot will skip over while looking for the next (or
+    /// previous) visible key before giving up with an error, instead of
+    /// scanning an unbounded run of stale versions on every call. `0`
+    /// (the default) leaves skipping unbounded, matching the historical
+    /// behavior.
+    pub fn set_max_skip(&mut self, max_skip: usize) {
+        self.max_skip = max_skip;
+    }
+
+    fn is_ttl_cf(&self, cf: CfName) -> bool {
+        if self.exempt_cfs.contains(cf) {
+            return false;
+        }
+        match &self.ttl_cfs {
+            Some(cfs) => cfs.contains(cf),
+            None => true,
+        }
+    }
+
     fn map_value(&self, value_with_ttl: Result<Option<Value>>) -> Result<Option<Value>> {
         match value_with_ttl? {
             Some(mut v) => {
-                let expire_ts = get_expire_ts(&v)?;
+                let expire_ts = self.codec.get_expire_ts(&v)?;
                 if expire_ts != 0 && expire_ts <= self.current_ts {
                     return Ok(None);
                 }
-                truncate_expire_ts(&mut v).unwrap();
+                self.codec.truncate_expire_ts(&mut v).unwrap();
                 Ok(Some(v))
             }
             None => Ok(None),
         }
     }
 
+    /// Like `get`, but also returns the key's `expire_ts` (0 when the key
+    /// has no TTL) alongside the stripped value. Already-expired keys are
+    /// filtered out, same as `get`.
+    pub fn get_with_ttl(&self, key: &Key) -> Result<Option<(Value, u64)>> {
+        match self.s.get(key)? {
+            Some(mut v) => {
+                let expire_ts = self.codec.get_expire_ts(&v)?;
+                if expire_ts != 0 && expire_ts <= self.current_ts {
+                    return Ok(None);
+                }
+                self.codec.truncate_expire_ts(&mut v).unwrap();
+                Ok(Some((v, expire_ts)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Like `get(key).is_some()`, but without the value clone and
+    /// `truncate_expire_ts` call that `get` only needs so it can hand back a
+    /// usable value -- this only has to answer whether a live value is
+    /// present, not what it is. Still returns `false` for an expired key.
+    pub fn exists(&self, key: &Key) -> Result<bool> {
+        self.exists_from(self.s.get(key)?)
+    }
+
+    /// Like `exists`, but on `cf` instead of the default CF, and skipping
+    /// expiry evaluation entirely on a CF that isn't TTL-enabled -- same
+    /// short-circuit `get_cf` takes.
+    pub fn exists_cf(&self, cf: CfName, key: &Key) -> Result<bool> {
+        if !self.is_ttl_cf(cf) {
+            return Ok(self.s.get_cf(cf, key)?.is_some());
+        }
+        self.exists_from(self.s.get_cf(cf, key)?)
+    }
+
+    fn exists_from(&self, value_with_ttl: Option<Value>) -> Result<bool> {
+        match value_with_ttl {
+            Some(v) => {
+                let expire_ts = self.codec.get_expire_ts(&v)?;
+                Ok(expire_ts == 0 || expire_ts > self.current_ts)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Like `get`, but forwards `opts` to the inner snapshot's `get_cf_opt`
+    /// on the default CF instead of always reading with the default
+    /// `ReadOptions`. Lets a caller request e.g. a memtable-only read
+    /// (`fill_cache(false)`) for a TTL-decoded value, the same tuning
+    /// `get_cf_opt` already exposes for a plain `get_cf`.
+    pub fn get_opt(&self, opts: ReadOptions, key: &Key) -> Result<Option<Value>> {
+        self.map_value(self.s.get_cf_opt(opts, CF_DEFAULT, key))
+    }
+
     pub fn get_key_ttl_cf(
         &self,
         cf: CfName,
@@ -64,7 +244,7 @@ impl<S: Snapshot> TTLSnapshot<S> {
         stats.data.flow_stats.read_bytes = key.as_encoded().len();
         if let Some(v) = value_with_ttl {
             stats.data.flow_stats.read_bytes += v.len();
-            let expire_ts = get_expire_ts(&v)?;
+            let expire_ts = self.codec.get_expire_ts(&v)?;
             if expire_ts == 0 {
                 return Ok(Some(0));
             }
@@ -76,40 +256,78 @@ impl<S: Snapshot> TTLSnapshot<S> {
         }
         Ok(None)
     }
+
+    /// Builds an iterator scoped to keys sharing `prefix` in `cf`. Compared
+    /// to a full-range iterator, this sets tight lower/upper bounds so the
+    /// engine can skip straight past runs of expired keys outside the
+    /// prefix instead of the TTL iterator filtering them out one at a time.
+    /// Correctness (expired-key filtering) is identical to `iter_cf`.
+    pub fn prefix_iter(&self, cf: CfName, prefix: &[u8]) -> Result<TTLIterator<S::Iter, C>> {
+        let lower_bound = KeyBuilder::from_vec(prefix.to_vec(), 0, 0);
+        let upper_bound = prefix_end(prefix).map(|end| KeyBuilder::from_vec(end, 0, 0));
+        let iter_opt = IterOptions::new(Some(lower_bound), upper_bound, false);
+        Ok(TTLIterator::new(
+            self.s.iter_cf(cf, iter_opt)?,
+            self.current_ts,
+            self.is_ttl_cf(cf),
+            self.codec.clone(),
+            self.max_skip,
+        ))
+    }
 }
 
-impl<S: Snapshot> From<S> for TTLSnapshot<S> {
+impl<S: Snapshot> From<S> for TTLSnapshot<S, DefaultExpiryCodec> {
     fn from(s: S) -> Self {
         TTLSnapshot {
             s,
             current_ts: current_ts(),
+            ttl_cfs: None,
+            exempt_cfs: std::collections::HashSet::default(),
+            codec: DefaultExpiryCodec,
+            max_skip: 0,
         }
     }
 }
 
-impl<S: Snapshot> Snapshot for TTLSnapshot<S> {
-    type Iter = TTLIterator<S::Iter>;
+impl<S: Snapshot, C: ExpiryCodec> Snapshot for TTLSnapshot<S, C> {
+    type Iter = TTLIterator<S::Iter, C>;
 
     fn get(&self, key: &Key) -> Result<Option<Value>> {
         self.map_value(self.s.get(key))
     }
 
     fn get_cf(&self, cf: CfName, key: &Key) -> Result<Option<Value>> {
+        if !self.is_ttl_cf(cf) {
+            return self.s.get_cf(cf, key);
+        }
         self.map_value(self.s.get_cf(cf, key))
     }
 
     fn get_cf_opt(&self, opts: ReadOptions, cf: CfName, key: &Key) -> Result<Option<Value>> {
+        if !self.is_ttl_cf(cf) {
+            return self.s.get_cf_opt(opts, cf, key);
+        }
         self.map_value(self.s.get_cf_opt(opts, cf, key))
     }
 
     fn iter(&self, iter_opt: IterOptions) -> Result<Self::Iter> {
-        Ok(TTLIterator::new(self.s.iter(iter_opt)?, self.current_ts))
+        let ttl = self.is_ttl_cf(CF_DEFAULT);
+        Ok(TTLIterator::new(
+            self.s.iter(iter_opt)?,
+            self.current_ts,
+            ttl,
+            self.codec.clone(),
+            self.max_skip,
+        ))
     }
 
     fn iter_cf(&self, cf: CfName, iter_opt: IterOptions) -> Result<Self::Iter> {
         Ok(TTLIterator::new(
             self.s.iter_cf(cf, iter_opt)?,
             self.current_ts,
+            self.is_ttl_cf(cf),
+            self.codec.clone(),
+            self.max_skip,
         ))
     }
 
@@ -133,32 +351,75 @@ impl<S: Snapshot> Snapshot for TTLSnapshot<S> {
     }
 }
 
-pub struct TTLIterator<I: Iterator> {
+pub struct TTLIterator<I: Iterator, C: ExpiryCodec = DefaultExpiryCodec> {
     i: I,
     current_ts: u64,
+    /// Whether the underlying CF carries TTL-encoded values. When `false`,
+    /// expiry filtering and value stripping are both skipped.
+    ttl: bool,
+    codec: C,
 
     skip_ttl: usize,
+    /// Entries skipped since the last `take_io_skip_hint`, separate from
+    /// `skip_ttl` (which accumulates for the whole iterator's lifetime for
+    /// the `TTL_TOMBSTONE` metric) so a caller driving a bounded time slice
+    /// can find out how much skipping happened since it last checked,
+    /// without disturbing that metric.
+    skip_since_hint: usize,
+    /// Maximum number of consecutive expired keys `find_valid_value` will
+    /// skip while looking for the next visible key before giving up with an
+    /// error, instead of turning what looks like a point read into an
+    /// unbounded scan over stale versions. `0` means unlimited.
+    max_skip: usize,
 }
 
-impl<I: Iterator> TTLIterator<I> {
-    fn new(i: I, current_ts: u64) -> Self {
+impl<I: Iterator, C: ExpiryCodec> TTLIterator<I, C> {
+    fn new(i: I, current_ts: u64, ttl: bool, codec: C, max_skip: usize) -> Self {
         TTLIterator {
             i,
             current_ts,
+            ttl,
+            codec,
             skip_ttl: 0,
+            skip_since_hint: 0,
+            max_skip,
         }
     }
 
+    /// Total number of expired keys skipped since this iterator was
+    /// constructed, for the caller to fold into its own scan statistics and
+    /// resource-group consumption accounting. Distinct from
+    /// `take_io_skip_hint`, which drains on read; this only ever grows.
+    pub fn skipped_expired(&self) -> u64 {
+        self.skip_ttl as u64
+    }
+
     fn find_valid_value(&mut self, mut res: Result<bool>, forward: bool) -> Result<bool> {
+        if !self.ttl {
+            return res;
+        }
+        let mut skipped = 0usize;
         loop {
             if res.is_err() {
                 break;
             }
 
             if *res.as_ref().unwrap() {
-                let expire_ts = get_expire_ts(self.i.value())?;
+                let expire_ts = self.codec.get_expire_ts(self.i.value())?;
                 if expire_ts != 0 && expire_ts <= self.current_ts {
                     self.skip_ttl += 1;
+                    self.skip_since_hint += 1;
+                    skipped += 1;
+                    if self.max_skip != 0 && skipped > self.max_skip {
+                        return Err(KvErrorInner::Other(box_err!(
+                            "TTL iterator skipped over {} expired keys without finding a \
+                             visible one, exceeding the configured limit of {}; the range \
+                             likely needs a compaction to reclaim the expired versions",
+                            skipped,
+                            self.max_skip
+                        ))
+                        .into());
+                    }
                     res = if forward {
                         self.i.next()
                     } else {
@@ -173,7 +434,7 @@ impl<I: Iterator> TTLIterator<I> {
     }
 }
 
-impl<I: Iterator> Drop for TTLIterator<I> {
+impl<I: Iterator, C: ExpiryCodec> Drop for TTLIterator<I, C> {
     fn drop(&mut self) {
         TTL_TOMBSTONE.with(|m| {
             *m.borrow_mut() += self.skip_ttl;
@@ -181,7 +442,7 @@ impl<I: Iterator> Drop for TTLIterator<I> {
     }
 }
 
-impl<I: Iterator> Iterator for TTLIterator<I> {
+impl<I: Iterator, C: ExpiryCodec> Iterator for TTLIterator<I, C> {
     fn next(&mut self) -> Result<bool> {
         let res = self.i.next();
         self.find_valid_value(res, true)
@@ -220,12 +481,19 @@ impl<I: Iterator> Iterator for TTLIterator<I> {
         self.i.validate_key(key)
     }
 
+    fn take_io_skip_hint(&mut self) -> usize {
+        std::mem::take(&mut self.skip_since_hint)
+    }
+
     fn key(&self) -> &[u8] {
         self.i.key()
     }
 
     fn value(&self) -> &[u8] {
-        strip_expire_ts(self.i.value())
+        if !self.ttl {
+            return self.i.value();
+        }
+        self.codec.strip_expire_ts(self.i.value())
     }
 }
 
@@ -237,6 +505,42 @@ mod tests {
 
     use engine_traits::util::append_expire_ts;
     use engine_traits::{SyncMutable, CF_DEFAULT};
+    use std::sync::{Arc, Mutex};
+
+    /// A `Snapshot` wrapper that records the `ReadOptions` passed to the
+    /// last `get_cf_opt` call, so a test can assert options reach the inner
+    /// snapshot without needing a real engine that behaves differently under
+    /// them.
+    #[derive(Clone)]
+    struct RecordingSnapshot<S: Snapshot> {
+        inner: S,
+        last_fill_cache: Arc<Mutex<Option<bool>>>,
+    }
+
+    impl<S: Snapshot> Snapshot for RecordingSnapshot<S> {
+        type Iter = S::Iter;
+
+        fn get(&self, key: &Key) -> Result<Option<Value>> {
+            self.inner.get(key)
+        }
+
+        fn get_cf(&self, cf: CfName, key: &Key) -> Result<Option<Value>> {
+            self.inner.get_cf(cf, key)
+        }
+
+        fn get_cf_opt(&self, opts: ReadOptions, cf: CfName, key: &Key) -> Result<Option<Value>> {
+            *self.last_fill_cache.lock().unwrap() = Some(opts.fill_cache());
+            self.inner.get_cf_opt(opts, cf, key)
+        }
+
+        fn iter(&self, iter_opt: IterOptions) -> Result<Self::Iter> {
+            self.inner.iter(iter_opt)
+        }
+
+        fn iter_cf(&self, cf: CfName, iter_opt: IterOptions) -> Result<Self::Iter> {
+            self.inner.iter_cf(cf, iter_opt)
+        }
+    }
 
     #[test]
     fn test_ttl_snapshot() {
@@ -304,6 +608,237 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_ttl_snapshot_exists_matches_get_is_some() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let engine = TestEngineBuilder::new()
+            .path(dir.path())
+            .ttl(true)
+            .build()
+            .unwrap();
+        let kvdb = engine.get_rocksdb();
+
+        // Live key.
+        let key1 = b"key1";
+        let mut value1 = b"value1".to_vec();
+        append_expire_ts(&mut value1, 110);
+        kvdb.put_cf(CF_DEFAULT, key1, &value1).unwrap();
+
+        // Expired key.
+        let key2 = b"key2";
+        let mut value2 = b"value2".to_vec();
+        append_expire_ts(&mut value2, 90);
+        kvdb.put_cf(CF_DEFAULT, key2, &value2).unwrap();
+
+        // Never-expiring key.
+        let key3 = b"key3";
+        let mut value3 = b"value3".to_vec();
+        append_expire_ts(&mut value3, 0);
+        kvdb.put_cf(CF_DEFAULT, key3, &value3).unwrap();
+
+        let snapshot = engine.snapshot(SnapContext::default()).unwrap();
+        let ttl_snapshot = TTLSnapshot::from(snapshot);
+
+        for key in [b"key1".as_ref(), b"key2".as_ref(), b"key3".as_ref(), b"missing".as_ref()] {
+            let key = Key::from_encoded_slice(key);
+            assert_eq!(
+                ttl_snapshot.exists(&key).unwrap(),
+                ttl_snapshot.get(&key).unwrap().is_some(),
+                "exists() disagreed with get(...).is_some() for {:?}",
+                key,
+            );
+            assert_eq!(
+                ttl_snapshot.exists_cf(CF_DEFAULT, &key).unwrap(),
+                ttl_snapshot.get_cf(CF_DEFAULT, &key).unwrap().is_some(),
+                "exists_cf() disagreed with get_cf(...).is_some() for {:?}",
+                key,
+            );
+        }
+        assert!(ttl_snapshot.exists(&Key::from_encoded_slice(b"key1")).unwrap());
+        assert!(!ttl_snapshot.exists(&Key::from_encoded_slice(b"key2")).unwrap());
+        assert!(ttl_snapshot.exists(&Key::from_encoded_slice(b"key3")).unwrap());
+        assert!(!ttl_snapshot.exists(&Key::from_encoded_slice(b"missing")).unwrap());
+    }
+
+    #[test]
+    fn test_ttl_snapshot_get_opt_forwards_options() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let engine = TestEngineBuilder::new()
+            .path(dir.path())
+            .ttl(true)
+            .build()
+            .unwrap();
+        let kvdb = engine.get_rocksdb();
+
+        let key1 = b"key1";
+        let mut value1 = b"value1".to_vec();
+        append_expire_ts(&mut value1, 110);
+        kvdb.put_cf(CF_DEFAULT, key1, &value1).unwrap();
+
+        let key2 = b"key2";
+        let mut value2 = b"value2".to_vec();
+        append_expire_ts(&mut value2, 90);
+        kvdb.put_cf(CF_DEFAULT, key2, &value2).unwrap();
+
+        let snapshot = engine.snapshot(SnapContext::default()).unwrap();
+        let last_fill_cache = Arc::new(Mutex::new(None));
+        let recording = RecordingSnapshot {
+            inner: snapshot,
+            last_fill_cache: last_fill_cache.clone(),
+        };
+        let ttl_snapshot = TTLSnapshot::from(recording);
+
+        let mut opts = ReadOptions::new();
+        opts.set_fill_cache(false);
+        assert_eq!(
+            ttl_snapshot
+                .get_opt(opts, &Key::from_encoded_slice(key1))
+                .unwrap(),
+            Some(b"value1".to_vec())
+        );
+        assert_eq!(*last_fill_cache.lock().unwrap(), Some(false));
+
+        // TTL semantics still hold with a non-default option set: an
+        // expired key stays filtered out regardless of `fill_cache`.
+        let mut opts = ReadOptions::new();
+        opts.set_fill_cache(true);
+        assert_eq!(
+            ttl_snapshot
+                .get_opt(opts, &Key::from_encoded_slice(key2))
+                .unwrap(),
+            None
+        );
+        assert_eq!(*last_fill_cache.lock().unwrap(), Some(true));
+    }
+
+    #[test]
+    fn test_ttl_snapshot_get_with_ttl() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let engine = TestEngineBuilder::new()
+            .path(dir.path())
+            .ttl(true)
+            .build()
+            .unwrap();
+        let kvdb = engine.get_rocksdb();
+
+        let key1 = b"key1";
+        let mut value1 = b"value1".to_vec();
+        append_expire_ts(&mut value1, 110);
+        kvdb.put_cf(CF_DEFAULT, key1, &value1).unwrap();
+
+        let key2 = b"key2";
+        let mut value2 = b"value2".to_vec();
+        append_expire_ts(&mut value2, 0);
+        kvdb.put_cf(CF_DEFAULT, key2, &value2).unwrap();
+
+        let key3 = b"key3";
+        let mut value3 = b"value3".to_vec();
+        append_expire_ts(&mut value3, 90);
+        kvdb.put_cf(CF_DEFAULT, key3, &value3).unwrap();
+
+        let snapshot = engine.snapshot(SnapContext::default()).unwrap();
+        let ttl_snapshot = TTLSnapshot::from(snapshot);
+
+        // A live TTL key returns its value and expire_ts.
+        assert_eq!(
+            ttl_snapshot
+                .get_with_ttl(&Key::from_encoded_slice(key1))
+                .unwrap(),
+            Some((b"value1".to_vec(), 110))
+        );
+        // A key with no TTL returns expire_ts 0.
+        assert_eq!(
+            ttl_snapshot
+                .get_with_ttl(&Key::from_encoded_slice(key2))
+                .unwrap(),
+            Some((b"value2".to_vec(), 0))
+        );
+        // An expired key is filtered out.
+        assert_eq!(
+            ttl_snapshot
+                .get_with_ttl(&Key::from_encoded_slice(key3))
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_ttl_snapshot_with_ttl_cfs() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let engine = TestEngineBuilder::new()
+            .path(dir.path())
+            .ttl(true)
+            .build()
+            .unwrap();
+        let kvdb = engine.get_rocksdb();
+
+        // A raw, TTL-suffixed value in the TTL-enabled default CF.
+        let mut ttl_value = b"value1".to_vec();
+        append_expire_ts(&mut ttl_value, 90);
+        kvdb.put_cf(CF_DEFAULT, b"key1", &ttl_value).unwrap();
+
+        // A plain value in the lock CF, which never carries a TTL suffix.
+        let raw_value = b"lock-value".to_vec();
+        kvdb.put_cf(engine_traits::CF_LOCK, b"key1", &raw_value)
+            .unwrap();
+
+        let snapshot = engine.snapshot(SnapContext::default()).unwrap();
+        let ttl_snapshot = TTLSnapshot::with_ttl_cfs(snapshot, vec![CF_DEFAULT]);
+
+        // The TTL-enabled CF still filters expired keys.
+        assert_eq!(
+            ttl_snapshot
+                .get_cf(CF_DEFAULT, &Key::from_encoded_slice(b"key1"))
+                .unwrap(),
+            None
+        );
+
+        // The non-TTL CF returns its raw value unchanged, with no attempt
+        // to decode a TTL suffix out of it.
+        assert_eq!(
+            ttl_snapshot
+                .get_cf(engine_traits::CF_LOCK, &Key::from_encoded_slice(b"key1"))
+                .unwrap(),
+            Some(raw_value)
+        );
+    }
+
+    #[test]
+    fn test_ttl_snapshot_exempt_cf_returns_raw_value() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let engine = TestEngineBuilder::new()
+            .path(dir.path())
+            .ttl(true)
+            .build()
+            .unwrap();
+        let kvdb = engine.get_rocksdb();
+
+        // A lock-CF value that happens to end in bytes that would parse as a
+        // plausible (non-expired) `expire_ts` if TTL-decoded.
+        let mut lock_value = b"lock-value".to_vec();
+        append_expire_ts(&mut lock_value, 110);
+        kvdb.put_cf(engine_traits::CF_LOCK, b"key1", &lock_value)
+            .unwrap();
+
+        let snapshot = engine.snapshot(SnapContext::default()).unwrap();
+        // `From` enables TTL decoding for every CF by default, so without
+        // the exemption this would strip the trailing bytes as an
+        // `expire_ts` suffix.
+        let ttl_snapshot = TTLSnapshot::from(snapshot).with_exempt_cfs(vec![engine_traits::CF_LOCK]);
+
+        assert_eq!(
+            ttl_snapshot
+                .get_cf(engine_traits::CF_LOCK, &Key::from_encoded_slice(b"key1"))
+                .unwrap(),
+            Some(lock_value.clone())
+        );
+        assert!(
+            ttl_snapshot
+                .exists_cf(engine_traits::CF_LOCK, &Key::from_encoded_slice(b"key1"))
+                .unwrap()
+        );
+    }
+
     #[test]
     fn test_ttl_iterator() {
         let dir = tempfile::TempDir::new().unwrap();
@@ -387,4 +922,303 @@ mod tests {
         assert_eq!(iter.key(), b"key1");
         assert_eq!(iter.value(), b"value1");
     }
+
+    #[test]
+    fn test_prefix_end() {
+        assert_eq!(prefix_end(b"key"), Some(b"kez".to_vec()));
+        assert_eq!(prefix_end(b"ke\xff"), Some(b"kf".to_vec()));
+        assert_eq!(prefix_end(b"\xff\xff"), None);
+        assert_eq!(prefix_end(b""), None);
+    }
+
+    #[test]
+    fn test_ttl_prefix_iter() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let engine = TestEngineBuilder::new()
+            .path(dir.path())
+            .ttl(true)
+            .build()
+            .unwrap();
+        let kvdb = engine.get_rocksdb();
+
+        for (key, expire_ts) in [
+            (b"a1".to_vec(), 110),
+            (b"a2".to_vec(), 90),
+            (b"b1".to_vec(), 110),
+        ] {
+            let mut value = b"value".to_vec();
+            append_expire_ts(&mut value, expire_ts);
+            kvdb.put_cf(CF_DEFAULT, &key, &value).unwrap();
+        }
+
+        let snapshot = engine.snapshot(SnapContext::default()).unwrap();
+        let ttl_snapshot = TTLSnapshot::from(snapshot);
+        let mut iter = ttl_snapshot.prefix_iter(CF_DEFAULT, b"a").unwrap();
+        assert_eq!(iter.seek_to_first().unwrap(), true);
+        assert_eq!(iter.key(), b"a1");
+        // a2 is expired, and b1 is outside the prefix; either way iteration
+        // should end here.
+        assert_eq!(iter.next().unwrap(), false);
+    }
+
+    /// A trivial alternate `ExpiryCodec` with a completely different byte
+    /// layout from `DefaultExpiryCodec`: `expire_ts` as a big-endian `u64`
+    /// *prefix* instead of a suffix. Exists only to prove expiry filtering
+    /// works through the `ExpiryCodec` abstraction, not because a prefix
+    /// layout is otherwise useful.
+    #[derive(Clone, Copy, Default)]
+    struct PrefixExpiryCodec;
+
+    impl ExpiryCodec for PrefixExpiryCodec {
+        fn get_expire_ts(&self, value_with_ttl: &[u8]) -> Result<u64> {
+            if value_with_ttl.len() < tikv_util::codec::number::U64_SIZE {
+                return Err(engine_traits::Error::Codec(tikv_util::codec::Error::ValueLength).into());
+            }
+            let mut ts = &value_with_ttl[..tikv_util::codec::number::U64_SIZE];
+            Ok(tikv_util::codec::number::decode_u64(&mut ts)?)
+        }
+
+        fn strip_expire_ts<'a>(&self, value_with_ttl: &'a [u8]) -> &'a [u8] {
+            &value_with_ttl[tikv_util::codec::number::U64_SIZE..]
+        }
+
+        fn truncate_expire_ts(&self, value_with_ttl: &mut Vec<u8>) -> Result<()> {
+            if value_with_ttl.len() < tikv_util::codec::number::U64_SIZE {
+                return Err(engine_traits::Error::Codec(tikv_util::codec::Error::ValueLength).into());
+            }
+            value_with_ttl.drain(..tikv_util::codec::number::U64_SIZE);
+            Ok(())
+        }
+    }
+
+    fn append_prefix_expire_ts(value: &[u8], expire_ts: u64) -> Vec<u8> {
+        use tikv_util::codec::number::NumberEncoder;
+        let mut encoded = Vec::with_capacity(8 + value.len());
+        encoded.encode_u64(expire_ts).unwrap();
+        encoded.extend_from_slice(value);
+        encoded
+    }
+
+    #[test]
+    fn test_ttl_snapshot_with_alternate_codec() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let engine = TestEngineBuilder::new()
+            .path(dir.path())
+            .ttl(true)
+            .build()
+            .unwrap();
+        let kvdb = engine.get_rocksdb();
+
+        let key1 = b"key1";
+        kvdb.put_cf(CF_DEFAULT, key1, &append_prefix_expire_ts(b"value1", 90))
+            .unwrap();
+        let key2 = b"key2";
+        kvdb.put_cf(CF_DEFAULT, key2, &append_prefix_expire_ts(b"value2", 110))
+            .unwrap();
+        let key3 = b"key3";
+        kvdb.put_cf(CF_DEFAULT, key3, &append_prefix_expire_ts(b"value3", 0))
+            .unwrap();
+
+        let snapshot = engine.snapshot(SnapContext::default()).unwrap();
+        let ttl_snapshot =
+            TTLSnapshot::with_codec(snapshot, vec![CF_DEFAULT], PrefixExpiryCodec);
+
+        // Expired: filtered out even though the expire_ts lives at the
+        // front of the value instead of the back.
+        assert_eq!(
+            ttl_snapshot.get(&Key::from_encoded_slice(key1)).unwrap(),
+            None
+        );
+        // Live: value comes back with the prefix stripped.
+        assert_eq!(
+            ttl_snapshot.get(&Key::from_encoded_slice(key2)).unwrap(),
+            Some(b"value2".to_vec())
+        );
+        // Never expires.
+        assert_eq!(
+            ttl_snapshot.get(&Key::from_encoded_slice(key3)).unwrap(),
+            Some(b"value3".to_vec())
+        );
+
+        // The iterator applies the same codec: key1 is skipped, key2 and
+        // key3 come back with their prefixes stripped.
+        let mut iter = ttl_snapshot
+            .iter(IterOptions::new(None, None, false))
+            .unwrap();
+        assert_eq!(iter.seek_to_first().unwrap(), true);
+        assert_eq!(iter.key(), b"key2");
+        assert_eq!(iter.value(), b"value2");
+        assert_eq!(iter.next().unwrap(), true);
+        assert_eq!(iter.key(), b"key3");
+        assert_eq!(iter.value(), b"value3");
+        assert_eq!(iter.next().unwrap(), false);
+    }
+
+    /// A scan over a range that is almost entirely expired keys must report
+    /// its skip work via `take_io_skip_hint` after each call, not only in the
+    /// `TTL_TOMBSTONE` metric when the iterator is dropped, so a caller
+    /// bounding its own work per time slice (see `raw::store`) counts the
+    /// skipped keys toward its slice. A single call still skips a whole run
+    /// of expired keys before it returns.
+    #[test]
+    fn test_ttl_iterator_reports_skip_hint_for_cooperative_yielding() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let engine = TestEngineBuilder::new()
+            .path(dir.path())
+            .ttl(true)
+            .build()
+            .unwrap();
+        let kvdb = engine.get_rocksdb();
+
+        const EXPIRED_COUNT: usize = 5000;
+        const YIELD_BOUNDARY: usize = 1024;
+        for i in 0..EXPIRED_COUNT {
+            let key = format!("key{:06}", i);
+            let mut value = format!("value{}", i).into_bytes();
+            append_expire_ts(&mut value, 90);
+            kvdb.put_cf(CF_DEFAULT, key.as_bytes(), &value).unwrap();
+        }
+        let live_key = format!("key{:06}", EXPIRED_COUNT);
+        let mut live_value = b"live".to_vec();
+        append_expire_ts(&mut live_value, 0);
+        kvdb.put_cf(CF_DEFAULT, live_key.as_bytes(), &live_value)
+            .unwrap();
+
+        let snapshot = engine.snapshot(SnapContext::default()).unwrap();
+        let ttl_snapshot = TTLSnapshot::from(snapshot);
+        let mut iter = ttl_snapshot
+            .iter(IterOptions::new(None, None, false))
+            .unwrap();
+
+        // `seek_to_first` alone has to skip every expired key before landing
+        // on the one live key, all within a single call.
+        assert_eq!(iter.seek_to_first().unwrap(), true);
+        assert_eq!(iter.key(), live_key.as_bytes());
+        assert_eq!(iter.value(), b"live");
+
+        let total_skipped = iter.take_io_skip_hint();
+        assert_eq!(total_skipped, EXPIRED_COUNT);
+        // A caller bounding its own work in slices of `YIELD_BOUNDARY` would
+        // have crossed that boundary several times over while this call ran.
+        assert!(total_skipped > YIELD_BOUNDARY);
+
+        // The hint drains on read: nothing new has been skipped since.
+        assert_eq!(iter.take_io_skip_hint(), 0);
+        assert_eq!(iter.next().unwrap(), false);
+        assert_eq!(iter.take_io_skip_hint(), 0);
+    }
+
+    /// A scan over a long run of expired keys that exceeds the configured
+    /// skip cap gives up with an error instead of silently turning into an
+    /// unbounded scan, so the caller can react (e.g. trigger a compaction)
+    /// rather than stalling.
+    #[test]
+    fn test_ttl_iterator_errors_when_skip_cap_exceeded() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let engine = TestEngineBuilder::new()
+            .path(dir.path())
+            .ttl(true)
+            .build()
+            .unwrap();
+        let kvdb = engine.get_rocksdb();
+
+        const EXPIRED_COUNT: usize = 5000;
+        const MAX_SKIP: usize = 100;
+        for i in 0..EXPIRED_COUNT {
+            let key = format!("key{:06}", i);
+            let mut value = format!("value{}", i).into_bytes();
+            append_expire_ts(&mut value, 90);
+            kvdb.put_cf(CF_DEFAULT, key.as_bytes(), &value).unwrap();
+        }
+        let live_key = format!("key{:06}", EXPIRED_COUNT);
+        let mut live_value = b"live".to_vec();
+        append_expire_ts(&mut live_value, 0);
+        kvdb.put_cf(CF_DEFAULT, live_key.as_bytes(), &live_value)
+            .unwrap();
+
+        let snapshot = engine.snapshot(SnapContext::default()).unwrap();
+        let mut ttl_snapshot = TTLSnapshot::from(snapshot);
+        ttl_snapshot.set_max_skip(MAX_SKIP);
+        let mut iter = ttl_snapshot
+            .iter(IterOptions::new(None, None, false))
+            .unwrap();
+
+        // The live key is far beyond MAX_SKIP expired keys away, so landing
+        // on it would require skipping more than the configured cap.
+        assert!(iter.seek_to_first().is_err());
+    }
+
+    /// With no cap configured, the historical behavior of skipping an
+    /// unbounded run of expired keys is unchanged.
+    #[test]
+    fn test_ttl_iterator_uncapped_by_default() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let engine = TestEngineBuilder::new()
+            .path(dir.path())
+            .ttl(true)
+            .build()
+            .unwrap();
+        let kvdb = engine.get_rocksdb();
+
+        const EXPIRED_COUNT: usize = 5000;
+        for i in 0..EXPIRED_COUNT {
+            let key = format!("key{:06}", i);
+            let mut value = format!("value{}", i).into_bytes();
+            append_expire_ts(&mut value, 90);
+            kvdb.put_cf(CF_DEFAULT, key.as_bytes(), &value).unwrap();
+        }
+        let live_key = format!("key{:06}", EXPIRED_COUNT);
+        let mut live_value = b"live".to_vec();
+        append_expire_ts(&mut live_value, 0);
+        kvdb.put_cf(CF_DEFAULT, live_key.as_bytes(), &live_value)
+            .unwrap();
+
+        let snapshot = engine.snapshot(SnapContext::default()).unwrap();
+        let ttl_snapshot = TTLSnapshot::from(snapshot);
+        let mut iter = ttl_snapshot
+            .iter(IterOptions::new(None, None, false))
+            .unwrap();
+
+        assert_eq!(iter.seek_to_first().unwrap(), true);
+        assert_eq!(iter.key(), live_key.as_bytes());
+    }
+
+    #[test]
+    fn test_ttl_iterator_skipped_expired_counts_since_construction() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let engine = TestEngineBuilder::new()
+            .path(dir.path())
+            .ttl(true)
+            .build()
+            .unwrap();
+        let kvdb = engine.get_rocksdb();
+
+        for (key, expire_ts) in [
+            (b"key1".to_vec(), 90),
+            (b"key2".to_vec(), 90),
+            (b"key3".to_vec(), 0),
+            (b"key4".to_vec(), 90),
+        ] {
+            let mut value = b"value".to_vec();
+            append_expire_ts(&mut value, expire_ts);
+            kvdb.put_cf(CF_DEFAULT, &key, &value).unwrap();
+        }
+
+        let snapshot = engine.snapshot(SnapContext::default()).unwrap();
+        let ttl_snapshot = TTLSnapshot::from(snapshot);
+        let mut iter = ttl_snapshot
+            .iter(IterOptions::new(None, None, false))
+            .unwrap();
+
+        assert_eq!(iter.skipped_expired(), 0);
+        assert_eq!(iter.seek_to_first().unwrap(), true);
+        assert_eq!(iter.key(), b"key3");
+        // key1 and key2 were skipped landing on the one live key.
+        assert_eq!(iter.skipped_expired(), 2);
+
+        assert_eq!(iter.next().unwrap(), false);
+        // key4 was skipped exhausting the range; the count keeps growing
+        // rather than resetting, unlike `take_io_skip_hint`.
+        assert_eq!(iter.skipped_expired(), 3);
+    }
 }
@@ -2,6 +2,7 @@
 
 //! Storage Transactions
 
+pub mod admission;
 pub mod commands;
 pub mod sched_pool;
 pub mod scheduler;
@@ -1,22 +1,43 @@
 // Copyright 2019 TiKV Project Authors. Licensed under Apache-2.0.
 
 use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use tikv_util::time::Duration;
+use std::task::{Context, Poll};
+use tikv_util::time::{Duration, Instant};
 
 use collections::HashMap;
-use file_system::{set_io_type, IOType};
+use file_system::{register_current_thread, set_io_type, IOType};
+use futures::channel::oneshot::Canceled;
+use futures::compat::Future01CompatExt;
 use prometheus::local::*;
-use tikv_util::yatp_pool::{FuturePool, PoolTicker, YatpPoolBuilder};
+use tikv_util::timer::GLOBAL_TIMER_HANDLE;
+use tikv_util::yatp_pool::{Full, FuturePool, PoolTicker, YatpPoolBuilder};
 
 use crate::storage::kv::{destroy_tls_engine, set_tls_engine, Engine, Statistics};
 use crate::storage::metrics::*;
 
+/// Polling interval used by `SchedPool::spawn_await_capacity` while waiting
+/// for the pool to have room for another task.
+const AWAIT_CAPACITY_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Default interval for the background thread that flushes thread-local
+/// write/scan stats even while the pool is idle. See
+/// `SchedPool::set_idle_flush_interval`.
+const DEFAULT_IDLE_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
 pub struct SchedLocalMetrics {
     local_scan_details: HashMap<&'static str, Statistics>,
     processing_read_duration: LocalHistogramVec,
     processing_write_duration: LocalHistogramVec,
     command_keyread_histogram_vec: LocalHistogramVec,
+    /// How many `tls_collect_scan_details` calls on this thread are
+    /// coalesced into one sampled contribution; see
+    /// `tls_set_scan_details_sample_rate`.
+    scan_details_sample_rate: u64,
+    scan_details_calls: u64,
 }
 
 thread_local! {
@@ -26,13 +47,66 @@ thread_local! {
             processing_read_duration: SCHED_PROCESSING_READ_HISTOGRAM_VEC.local(),
             processing_write_duration: SCHED_PROCESSING_WRITE_HISTOGRAM_VEC.local(),
             command_keyread_histogram_vec: KV_COMMAND_KEYREAD_HISTOGRAM_VEC.local(),
+            scan_details_sample_rate: 1,
+            scan_details_calls: 0,
         }
     );
 }
 
+/// A read-only, allocation-light snapshot of a [`SchedPool`]'s in-flight
+/// task accounting, meant for asserting on scheduler fairness/backpressure
+/// behavior in tests.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SchedPoolStats {
+    pub inflight: i64,
+    pub spawned: u64,
+    pub rejected: u64,
+}
+
 #[derive(Clone)]
 pub struct SchedPool {
     pub pool: FuturePool,
+    spawned: Arc<AtomicU64>,
+    rejected: Arc<AtomicU64>,
+    idle_flush_interval_ms: Arc<AtomicU64>,
+}
+
+/// A snapshot of `SchedPool::pool`'s activity, for observability tooling
+/// that wants queue depth and thread utilization without reaching into the
+/// public `pool` field and having to know which of `FuturePool`'s several
+/// counters means what.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PoolMetrics {
+    pub running_threads: usize,
+    pub queued_tasks: usize,
+    pub completed_tasks: u64,
+}
+
+/// Wraps a future so it short-circuits once `deadline` has passed, checked
+/// with the cheap `Instant::now_coarse` before every poll -- including the
+/// very first one, so a future spawned with an already-past deadline never
+/// runs any of its body at all. Meant for abandoned commands: once the
+/// client that would have used the result is long gone, there is no point
+/// spending pool capacity running it to completion.
+struct DeadlineFuture<F> {
+    future: Pin<Box<F>>,
+    deadline: Option<Instant>,
+}
+
+impl<F: Future> Future for DeadlineFuture<F> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if let Some(deadline) = self.deadline {
+            if Instant::now_coarse() >= deadline {
+                return Poll::Ready(());
+            }
+        }
+        match self.future.as_mut().poll(cx) {
+            Poll::Ready(_) => Poll::Ready(()),
+            Poll::Pending => Poll::Pending,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -46,15 +120,29 @@ impl PoolTicker for SchedTicker {
 
 impl SchedPool {
     pub fn new<E: Engine>(engine: E, pool_size: usize, name_prefix: &str) -> Self {
+        Self::with_max_tasks(engine, pool_size, std::usize::MAX, name_prefix)
+    }
+
+    /// Like `new`, but also bounds the number of tasks the pool will accept
+    /// before `spawn` starts returning `Full`. Split out from `new` so tests
+    /// can exercise backpressure without needing a real saturating workload.
+    fn with_max_tasks<E: Engine>(
+        engine: E,
+        pool_size: usize,
+        max_tasks: usize,
+        name_prefix: &str,
+    ) -> Self {
         let engine = Arc::new(Mutex::new(engine));
         let pool = YatpPoolBuilder::new(SchedTicker {})
             .thread_count(pool_size, pool_size)
+            .max_tasks(max_tasks)
             .name_prefix(name_prefix)
             // Safety: by setting `after_start` and `before_stop`, `FuturePool` ensures
             // the tls_engine invariants.
             .after_start(move || {
                 set_tls_engine(engine.lock().unwrap().clone());
                 set_io_type(IOType::ForegroundWrite);
+                register_current_thread();
             })
             .before_stop(move || unsafe {
                 // Safety: we ensure the `set_` and `destroy_` calls use the same engine type.
@@ -62,14 +150,231 @@ impl SchedPool {
                 tls_flush();
             })
             .build_future_pool();
-        SchedPool { pool }
+        let spawned = Arc::new(AtomicU64::new(0));
+        let rejected = Arc::new(AtomicU64::new(0));
+        let idle_flush_interval_ms = Arc::new(AtomicU64::new(
+            DEFAULT_IDLE_FLUSH_INTERVAL.as_millis() as u64
+        ));
+
+        // `SchedTicker::on_tick` only fires from inside `YatpPoolRunner::handle`,
+        // i.e. after a task finishes, so a pool that goes idle holds onto
+        // unreported thread-local stats until the next task arrives. This
+        // background thread flushes on its own schedule instead, independent
+        // of task activity. It self-terminates once every clone of this
+        // `SchedPool` is gone, tracked the same way `ImportModeSwitcher`
+        // tracks its owner: a `Weak` reference that stops upgrading once the
+        // last strong reference (here, `spawned`) is dropped.
+        let alive = Arc::downgrade(&spawned);
+        let flush_pool = pool.clone();
+        let flush_interval_ms = idle_flush_interval_ms.clone();
+        let metrics_pool_name = name_prefix.to_string();
+        std::thread::Builder::new()
+            .name(format!("{}-idle-flush", name_prefix))
+            .spawn(move || {
+                while alive.upgrade().is_some() {
+                    let interval =
+                        Duration::from_millis(flush_interval_ms.load(Ordering::Relaxed).max(1));
+                    std::thread::sleep(interval);
+                    if alive.upgrade().is_none() {
+                        break;
+                    }
+                    // Best-effort, same as `flush_metrics`: schedule one
+                    // flush per worker and move on without waiting, since
+                    // nobody is blocked on this running promptly.
+                    let workers = flush_pool.get_pool_size().max(1);
+                    for _ in 0..workers {
+                        let _ = flush_pool.spawn(async { tls_flush() });
+                    }
+
+                    let metrics = pool_metrics_of(&flush_pool);
+                    SCHED_POOL_RUNNING_TASKS_GAUGE_VEC
+                        .with_label_values(&[&metrics_pool_name])
+                        .set(metrics.running_threads as i64);
+                    SCHED_POOL_QUEUED_TASKS_GAUGE_VEC
+                        .with_label_values(&[&metrics_pool_name])
+                        .set(metrics.queued_tasks as i64);
+                }
+            })
+            .unwrap();
+
+        SchedPool {
+            pool,
+            spawned,
+            rejected,
+            idle_flush_interval_ms,
+        }
+    }
+
+    /// Configures how often the background thread flushes thread-local
+    /// stats while the pool is idle. Defaults to `DEFAULT_IDLE_FLUSH_INTERVAL`.
+    pub fn set_idle_flush_interval(&self, interval: Duration) {
+        self.idle_flush_interval_ms
+            .store(interval.as_millis().max(1) as u64, Ordering::Relaxed);
+    }
+
+    /// Spawns a future onto the pool, tracking it for `stats_snapshot`.
+    pub fn spawn<F>(&self, future: F) -> Result<(), Full>
+    where
+        F: std::future::Future + Send + 'static,
+    {
+        match self.pool.spawn(future) {
+            Ok(()) => {
+                self.spawned.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+            Err(e) => {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                Err(e)
+            }
+        }
     }
+
+    /// Like `spawn`, but the wrapped future short-circuits once `deadline`
+    /// has passed instead of running to completion, releasing the pool slot
+    /// for live work. `deadline == None` behaves exactly like `spawn`. See
+    /// `DeadlineFuture`.
+    pub fn spawn_with_deadline<F>(&self, future: F, deadline: Option<Instant>) -> Result<(), Full>
+    where
+        F: std::future::Future + Send + 'static,
+    {
+        self.spawn(DeadlineFuture {
+            future: Box::pin(future),
+            deadline,
+        })
+    }
+
+    /// Awaits until the pool has room for another task, then spawns
+    /// `future` onto it. Unlike `spawn`, which fails immediately with
+    /// `Full` once the pool is saturated and leaves retrying to the caller,
+    /// this gives an async producer natural backpressure: it simply stalls
+    /// until capacity frees up instead of having to poll `spawn` itself.
+    pub async fn spawn_await_capacity<F>(&self, future: F) -> Result<(), Canceled>
+    where
+        F: std::future::Future + Send + 'static,
+    {
+        while !self.pool.has_capacity() {
+            GLOBAL_TIMER_HANDLE
+                .delay(std::time::Instant::now() + AWAIT_CAPACITY_POLL_INTERVAL)
+                .compat()
+                .await
+                .unwrap();
+        }
+        // `has_capacity` only checks the moment before the actual `spawn`,
+        // so a concurrent producer can still win the race and leave the
+        // pool full by the time we get here. `FuturePool::spawn` drops the
+        // future rather than handing it back on `Full`, so there is nothing
+        // left to retry with; report it as canceled instead of silently
+        // discarding it.
+        self.spawn(future).map_err(|_| Canceled)
+    }
+
+    /// Forces a best-effort flush of the thread-local scan/duration metrics
+    /// accumulated by `tls_collect_*` on every pool worker, instead of
+    /// waiting for the next `SchedTicker::on_tick`.
+    ///
+    /// This works by scheduling one flush task per worker thread and waiting
+    /// for all of them to run; since yatp gives no way to target a specific
+    /// thread, it's only best-effort; a worker that's busy running a very
+    /// long task won't pick up its flush task until that task finishes, so
+    /// this can undercount if called while the pool is heavily loaded.
+    pub fn flush_metrics(&self) {
+        let workers = self.pool.get_pool_size().max(1);
+        let (tx, rx) = std::sync::mpsc::channel();
+        for _ in 0..workers {
+            let tx = tx.clone();
+            if self
+                .pool
+                .spawn(async move {
+                    tls_flush();
+                    let _ = tx.send(());
+                })
+                .is_err()
+            {
+                break;
+            }
+        }
+        drop(tx);
+        while rx.recv().is_ok() {}
+    }
+
+    /// Configures how many `tls_collect_scan_details` calls each worker
+    /// coalesces into one sampled contribution, see
+    /// `tls_set_scan_details_sample_rate`. Applied to every worker thread
+    /// the same best-effort way `flush_metrics` visits them.
+    pub fn set_scan_details_sample_rate(&self, rate: u64) {
+        let workers = self.pool.get_pool_size().max(1);
+        let (tx, rx) = std::sync::mpsc::channel();
+        for _ in 0..workers {
+            let tx = tx.clone();
+            if self
+                .pool
+                .spawn(async move {
+                    tls_set_scan_details_sample_rate(rate);
+                    let _ = tx.send(());
+                })
+                .is_err()
+            {
+                break;
+            }
+        }
+        drop(tx);
+        while rx.recv().is_ok() {}
+    }
+
+    pub fn stats_snapshot(&self) -> SchedPoolStats {
+        SchedPoolStats {
+            inflight: self.pool.get_running_task_count() as i64,
+            spawned: self.spawned.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Reports `pool`'s current activity: how many worker threads are busy,
+    /// how many spawned tasks are still waiting for one to free up, and how
+    /// many have run to completion so far.
+    ///
+    /// `FuturePool` doesn't track queued and actively-running tasks
+    /// separately -- `get_running_task_count` really means "spawned but not
+    /// yet completed" -- so `queued_tasks` here is derived: once in-flight
+    /// tasks outnumber the pool's threads, the excess must be waiting for a
+    /// free worker rather than running.
+    pub fn pool_metrics(&self) -> PoolMetrics {
+        pool_metrics_of(&self.pool)
+    }
+}
+
+fn pool_metrics_of(pool: &FuturePool) -> PoolMetrics {
+    let pool_size = pool.get_pool_size();
+    let in_flight = pool.get_running_task_count();
+    PoolMetrics {
+        running_threads: in_flight.min(pool_size),
+        queued_tasks: in_flight.saturating_sub(pool_size),
+        completed_tasks: pool.get_handled_task_count(),
+    }
+}
+
+/// Sets how many `tls_collect_scan_details` calls made from the current
+/// thread are coalesced into one sampled contribution -- scaled back up by
+/// the same factor on flush, so reported totals stay proportional. A rate
+/// of 1 (the default) samples every call for full fidelity; a higher rate
+/// cuts the per-command merge cost under very high QPS at the cost of
+/// quantized totals.
+pub fn tls_set_scan_details_sample_rate(rate: u64) {
+    TLS_SCHED_METRICS.with(|m| m.borrow_mut().scan_details_sample_rate = rate.max(1));
 }
 
 pub fn tls_collect_scan_details(cmd: &'static str, stats: &Statistics) {
     TLS_SCHED_METRICS.with(|m| {
-        m.borrow_mut()
-            .local_scan_details
+        let mut m = m.borrow_mut();
+        let rate = m.scan_details_sample_rate;
+        if rate > 1 {
+            let call = m.scan_details_calls;
+            m.scan_details_calls = call.wrapping_add(1);
+            if call % rate != 0 {
+                return;
+            }
+        }
+        m.local_scan_details
             .entry(cmd)
             .or_insert_with(Default::default)
             .add(stats);
@@ -79,12 +384,13 @@ pub fn tls_collect_scan_details(cmd: &'static str, stats: &Statistics) {
 pub fn tls_flush() {
     TLS_SCHED_METRICS.with(|m| {
         let mut m = m.borrow_mut();
+        let rate = m.scan_details_sample_rate as i64;
         for (cmd, stat) in m.local_scan_details.drain() {
             for (cf, cf_details) in stat.details().iter() {
                 for (tag, count) in cf_details.iter() {
                     KV_COMMAND_SCAN_DETAILS
                         .with_label_values(&[cmd, *cf, *tag])
-                        .inc_by(*count as i64);
+                        .inc_by(*count as i64 * rate);
                 }
             }
         }
@@ -111,3 +417,309 @@ pub fn tls_collect_keyread_histogram_vec(cmd: &str, count: f64) {
             .observe(count);
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::TestEngineBuilder;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn test_stats_snapshot() {
+        let engine = TestEngineBuilder::new().build().unwrap();
+        let pool = SchedPool::new(engine, 2, "test-sched-pool");
+
+        let stats = pool.stats_snapshot();
+        assert_eq!(stats, SchedPoolStats {
+            inflight: 0,
+            spawned: 0,
+            rejected: 0,
+        });
+
+        let (tx, rx) = channel();
+        for _ in 0..3 {
+            let tx = tx.clone();
+            pool.spawn(async move {
+                tx.send(()).unwrap();
+            })
+            .unwrap();
+        }
+        for _ in 0..3 {
+            rx.recv().unwrap();
+        }
+
+        let stats = pool.stats_snapshot();
+        assert_eq!(stats.spawned, 3);
+        assert_eq!(stats.rejected, 0);
+    }
+
+    #[test]
+    fn test_flush_metrics() {
+        let engine = TestEngineBuilder::new().build().unwrap();
+        // A single worker, so `flush_metrics`' one-task-per-worker flush is
+        // sure to land on the thread that collected the stats.
+        let pool = SchedPool::new(engine, 1, "test-sched-pool-flush");
+
+        let mut stats = Statistics::default();
+        stats.data.get = 3;
+
+        let (tx, rx) = channel();
+        pool.spawn(async move {
+            tls_collect_scan_details("test_flush_metrics", &stats);
+            tx.send(()).unwrap();
+        })
+        .unwrap();
+        rx.recv().unwrap();
+
+        pool.flush_metrics();
+
+        let after = KV_COMMAND_SCAN_DETAILS
+            .with_label_values(&["test_flush_metrics", "default", "get"])
+            .get();
+        assert_eq!(after, 3);
+    }
+
+    #[test]
+    fn test_idle_flush_reports_stats_without_new_tasks() {
+        let engine = TestEngineBuilder::new().build().unwrap();
+        let pool = SchedPool::new(engine, 2, "test-sched-pool-idle-flush");
+        pool.set_idle_flush_interval(std::time::Duration::from_millis(20));
+
+        let mut stats = Statistics::default();
+        stats.data.get = 3;
+
+        let (tx, rx) = channel();
+        pool.spawn(async move {
+            tls_collect_scan_details("test_idle_flush_reports_stats_without_new_tasks", &stats);
+            tx.send(()).unwrap();
+        })
+        .unwrap();
+        rx.recv().unwrap();
+
+        // The pool goes idle here: no more tasks are spawned, so
+        // `SchedTicker::on_tick` never fires again on its own. Only the
+        // background idle-flush thread can report the stats collected
+        // above.
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        loop {
+            let after = KV_COMMAND_SCAN_DETAILS
+                .with_label_values(&[
+                    "test_idle_flush_reports_stats_without_new_tasks",
+                    "default",
+                    "get",
+                ])
+                .get();
+            if after == 3 {
+                break;
+            }
+            assert!(
+                std::time::Instant::now() < deadline,
+                "idle flush never reported stats, got {}",
+                after
+            );
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+    }
+
+    #[test]
+    fn test_scan_details_sample_rate_exact_by_default() {
+        let engine = TestEngineBuilder::new().build().unwrap();
+        // A single worker; see `test_flush_metrics`.
+        let pool = SchedPool::new(engine, 1, "test-sched-pool-sample-exact");
+
+        let mut stats = Statistics::default();
+        stats.data.get = 3;
+
+        let (tx, rx) = channel();
+        pool.spawn(async move {
+            for _ in 0..5 {
+                tls_collect_scan_details("test_scan_details_sample_rate_exact_by_default", &stats);
+            }
+            tx.send(()).unwrap();
+        })
+        .unwrap();
+        rx.recv().unwrap();
+        pool.flush_metrics();
+
+        let after = KV_COMMAND_SCAN_DETAILS
+            .with_label_values(&[
+                "test_scan_details_sample_rate_exact_by_default",
+                "default",
+                "get",
+            ])
+            .get();
+        assert_eq!(after, 15);
+    }
+
+    #[test]
+    fn test_scan_details_sample_rate_scales_totals() {
+        let engine = TestEngineBuilder::new().build().unwrap();
+        let pool = SchedPool::new(engine, 1, "test-sched-pool-sample-scaled");
+        pool.set_scan_details_sample_rate(4);
+
+        let mut stats = Statistics::default();
+        stats.data.get = 3;
+
+        let (tx, rx) = channel();
+        pool.spawn(async move {
+            // Only the first of every 4 calls is actually sampled; the
+            // other 3 are dropped and accounted for by scaling on flush.
+            for _ in 0..8 {
+                tls_collect_scan_details("test_scan_details_sample_rate_scales_totals", &stats);
+            }
+            tx.send(()).unwrap();
+        })
+        .unwrap();
+        rx.recv().unwrap();
+        pool.flush_metrics();
+
+        let after = KV_COMMAND_SCAN_DETAILS
+            .with_label_values(&[
+                "test_scan_details_sample_rate_scales_totals",
+                "default",
+                "get",
+            ])
+            .get();
+        // 2 samples taken (calls 0 and 4), each scaled by the rate of 4.
+        assert_eq!(after, 2 * 4 * 3);
+    }
+
+    #[test]
+    fn test_spawn_with_deadline_skips_body_once_past() {
+        let engine = TestEngineBuilder::new().build().unwrap();
+        let pool = SchedPool::new(engine, 1, "test-sched-pool-deadline");
+
+        // A deadline already in the past: the future must finish quickly
+        // without ever running its body.
+        let (tx, rx) = channel();
+        let past = Instant::now_coarse() - std::time::Duration::from_secs(10);
+        pool.spawn_with_deadline(
+            async move {
+                tx.send(()).unwrap();
+            },
+            Some(past),
+        )
+        .unwrap();
+        assert!(rx.recv_timeout(std::time::Duration::from_secs(5)).is_err());
+
+        // A future nowhere near its deadline still runs normally.
+        let (tx, rx) = channel();
+        let far_future = Instant::now_coarse() + std::time::Duration::from_secs(60);
+        pool.spawn_with_deadline(
+            async move {
+                tx.send(()).unwrap();
+            },
+            Some(far_future),
+        )
+        .unwrap();
+        rx.recv_timeout(std::time::Duration::from_secs(5)).unwrap();
+
+        // No deadline at all behaves exactly like `spawn`.
+        let (tx, rx) = channel();
+        pool.spawn_with_deadline(
+            async move {
+                tx.send(()).unwrap();
+            },
+            None,
+        )
+        .unwrap();
+        rx.recv_timeout(std::time::Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn test_pool_metrics_tracks_queued_running_and_completed() {
+        let engine = TestEngineBuilder::new().build().unwrap();
+        // A single worker: the second and third spawned tasks must queue
+        // behind the first instead of running concurrently.
+        let pool = SchedPool::new(engine, 1, "test-sched-pool-metrics");
+
+        let metrics = pool.pool_metrics();
+        assert_eq!(metrics.running_threads, 0);
+        assert_eq!(metrics.queued_tasks, 0);
+        assert_eq!(metrics.completed_tasks, 0);
+
+        let (hold_tx, hold_rx) = channel::<()>();
+        let hold_rx = Arc::new(Mutex::new(hold_rx));
+        for _ in 0..3 {
+            let hold_rx = hold_rx.clone();
+            pool.spawn(async move {
+                hold_rx.lock().unwrap().recv().unwrap();
+            })
+            .unwrap();
+        }
+
+        // Wait for the pool to actually pick up the first task, since
+        // `spawn` returning doesn't guarantee the worker has started it yet.
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        loop {
+            let metrics = pool.pool_metrics();
+            if metrics.running_threads == 1 && metrics.queued_tasks == 2 {
+                break;
+            }
+            assert!(
+                std::time::Instant::now() < deadline,
+                "pool never settled into 1 running / 2 queued, got {:?}",
+                metrics
+            );
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        // Release all three tasks and wait for them to finish.
+        hold_tx.send(()).unwrap();
+        hold_tx.send(()).unwrap();
+        hold_tx.send(()).unwrap();
+
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        loop {
+            let metrics = pool.pool_metrics();
+            if metrics.completed_tasks == 3 {
+                assert_eq!(metrics.running_threads, 0);
+                assert_eq!(metrics.queued_tasks, 0);
+                break;
+            }
+            assert!(
+                std::time::Instant::now() < deadline,
+                "pool never reported all 3 tasks completed, got {:?}",
+                metrics
+            );
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_spawn_await_capacity() {
+        let engine = TestEngineBuilder::new().build().unwrap();
+        // A single worker, room for exactly one task at a time.
+        let pool = SchedPool::with_max_tasks(engine, 1, 1, "test-sched-pool-capacity");
+
+        // Saturate the pool with a task blocked on `hold_rx`.
+        let (hold_tx, hold_rx) = channel();
+        pool.spawn(async move {
+            hold_rx.recv().unwrap();
+        })
+        .unwrap();
+        assert!(!pool.pool.has_capacity());
+
+        // A second producer awaits capacity instead of failing outright.
+        let (done_tx, done_rx) = channel();
+        let pool_clone = pool.clone();
+        std::thread::spawn(move || {
+            futures::executor::block_on(pool_clone.spawn_await_capacity(async move {
+                done_tx.send(()).unwrap();
+            }))
+            .unwrap();
+        });
+
+        // The awaiting spawn hasn't happened yet: the pool is still full and
+        // nothing has been sent on `done_rx`.
+        assert!(done_rx.try_recv().is_err());
+
+        // Drain the blocking task, freeing up capacity.
+        hold_tx.send(()).unwrap();
+
+        // The awaiting spawn eventually goes through once capacity frees up.
+        done_rx
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap();
+    }
+}
@@ -29,6 +29,7 @@ use std::u64;
 use collections::HashMap;
 use concurrency_manager::{ConcurrencyManager, KeyHandleGuard};
 use kvproto::kvrpcpb::{CommandPri, ExtraOp};
+use resource_control::{ResourceConsumeType, ResourceController, ResourceGroupManager};
 use tikv_util::{callback::must_call, time::Instant};
 use txn_types::TimeStamp;
 
@@ -42,6 +43,7 @@ use crate::storage::metrics::{
     SCHED_CONTEX_GAUGE, SCHED_HISTOGRAM_VEC_STATIC, SCHED_LATCH_HISTOGRAM_VEC,
     SCHED_STAGE_COUNTER_VEC, SCHED_TOO_BUSY_COUNTER_VEC, SCHED_WRITING_BYTES_GAUGE,
 };
+use crate::storage::txn::admission::{AdmissionController, AdmissionDecision};
 use crate::storage::txn::commands::{ResponsePolicy, WriteContext, WriteResult};
 use crate::storage::txn::{
     commands::Command,
@@ -56,6 +58,14 @@ use crate::storage::{
 
 const TASKS_SLOTS_NUM: usize = 1 << 12; // 4096 slots.
 
+// Every command is currently attributed to `DEFAULT_RESOURCE_GROUP_NAME` --
+// there's no per-request resource group tag on `Command` yet -- so these
+// generous defaults mean the admission gate only actually bites once an
+// operator lowers `default`'s `ru_quota` (or `AdmissionController` gains a
+// tighter default), rather than throttling untagged traffic out of the box.
+const DEFAULT_ADMISSION_BUCKET_CAPACITY: f64 = 10_000.0;
+const DEFAULT_ADMISSION_REFILL_PER_SEC: f64 = 10_000.0;
+
 /// Task is a running command.
 pub(super) struct Task {
     pub(super) cid: u64,
@@ -101,6 +111,14 @@ struct TaskContext {
     latch_timer: Instant,
     // Total duration of a command.
     _cmd_timer: CmdTimer,
+    // The resource group whose admission slot this task currently holds, if
+    // any -- `Some` from the moment `AdmissionController::admit` accepted it
+    // until whichever completion handler finally releases it. Carried
+    // forward (not released) across a `ProcessResult::NextCommand` hop, so a
+    // multi-command chain (e.g. acquire-pessimistic-lock -> prewrite) holds
+    // exactly one admission slot for its whole lifetime instead of one per
+    // internal `Command`.
+    admission_group: Option<String>,
 }
 
 impl TaskContext {
@@ -129,6 +147,7 @@ impl TaskContext {
                 tag,
                 begin: Instant::now_coarse(),
             },
+            admission_group: None,
         }
     }
 
@@ -167,6 +186,11 @@ struct SchedulerInner<L: LockManager> {
     pipelined_pessimistic_lock: Arc<AtomicBool>,
 
     enable_async_apply_prewrite: bool,
+
+    // `None` unless a `ResourceGroupManager` was supplied to `Scheduler::new`,
+    // e.g. from a test builder that doesn't wire one up.
+    resource_ctl: Option<Arc<ResourceController>>,
+    admission: Option<Arc<AdmissionController>>,
 }
 
 #[inline]
@@ -260,6 +284,7 @@ impl<E: Engine, L: LockManager> Scheduler<E, L> {
         sched_pending_write_threshold: usize,
         pipelined_pessimistic_lock: Arc<AtomicBool>,
         enable_async_apply_prewrite: bool,
+        resource_manager: Option<Arc<ResourceGroupManager>>,
     ) -> Self {
         let t = Instant::now_coarse();
         let mut task_slots = Vec::with_capacity(TASKS_SLOTS_NUM);
@@ -267,21 +292,44 @@ impl<E: Engine, L: LockManager> Scheduler<E, L> {
             task_slots.push(Mutex::new(Default::default()).into());
         }
 
+        let worker_pool = SchedPool::new(engine.clone(), worker_pool_size, "sched-worker-pool");
+        let high_priority_pool = SchedPool::new(
+            engine.clone(),
+            std::cmp::max(1, worker_pool_size / 2),
+            "sched-high-pri-pool",
+        );
+
+        // Every command currently runs as `DEFAULT_RESOURCE_GROUP_NAME` (see
+        // the constants above) -- both derived from the same manager so a
+        // resource group's `ru_quota` sizes its admission bucket the same
+        // way it sizes its scheduling weight.
+        let resource_ctl = resource_manager
+            .as_ref()
+            .map(|m| m.derive_controller("sched".to_string()));
+        let admission = resource_manager.as_ref().map(|m| {
+            Arc::new(
+                AdmissionController::new(
+                    worker_pool.clone(),
+                    DEFAULT_ADMISSION_BUCKET_CAPACITY,
+                    DEFAULT_ADMISSION_REFILL_PER_SEC,
+                )
+                .with_resource_group_manager(m.clone()),
+            )
+        });
+
         let inner = Arc::new(SchedulerInner {
             task_slots,
             id_alloc: AtomicU64::new(0).into(),
             latches: Latches::new(concurrency),
             running_write_bytes: AtomicUsize::new(0).into(),
             sched_pending_write_threshold,
-            worker_pool: SchedPool::new(engine.clone(), worker_pool_size, "sched-worker-pool"),
-            high_priority_pool: SchedPool::new(
-                engine.clone(),
-                std::cmp::max(1, worker_pool_size / 2),
-                "sched-high-pri-pool",
-            ),
+            worker_pool,
+            high_priority_pool,
             lock_mgr,
             concurrency_manager,
             pipelined_pessimistic_lock,
+            resource_ctl,
+            admission,
             enable_async_apply_prewrite,
         });
 
@@ -301,7 +349,29 @@ impl<E: Engine, L: LockManager> Scheduler<E, L> {
             });
             return;
         }
-        self.schedule_command(cmd, callback);
+
+        // Admission control, on top of the flow-control check above: a
+        // resource group that's burned through its token bucket (or its
+        // concurrency cap) is rejected here, before it ever takes a latch
+        // slot, rather than being let in and only throttled by virtual-time
+        // ordering once it's already running.
+        let admission_group = if let Some(admission) = &self.inner.admission {
+            let group = resource_control::DEFAULT_RESOURCE_GROUP_NAME;
+            match admission.admit(group) {
+                AdmissionDecision::Admit => Some(group.to_string()),
+                AdmissionDecision::Reject { .. } => {
+                    SCHED_TOO_BUSY_COUNTER_VEC.get(cmd.tag()).inc();
+                    callback.execute(ProcessResult::Failed {
+                        err: StorageError::from(StorageErrorInner::SchedTooBusy),
+                    });
+                    return;
+                }
+            }
+        } else {
+            None
+        };
+
+        self.schedule_command(cmd, callback, admission_group);
     }
 
     /// Releases all the latches held by a command.
@@ -312,7 +382,12 @@ impl<E: Engine, L: LockManager> Scheduler<E, L> {
         }
     }
 
-    fn schedule_command(&self, cmd: Command, callback: StorageCallback) {
+    fn schedule_command(
+        &self,
+        cmd: Command,
+        callback: StorageCallback,
+        admission_group: Option<String>,
+    ) {
         let cid = self.inner.gen_id();
         debug!("received new command"; "cid" => cid, "cmd" => ?cmd);
 
@@ -327,6 +402,7 @@ impl<E: Engine, L: LockManager> Scheduler<E, L> {
         let tctx = task_slot
             .entry(cid)
             .or_insert_with(|| self.inner.new_task_context(Task::new(cid, cmd), callback));
+        tctx.admission_group = admission_group;
         if self.inner.latches.acquire(&mut tctx.lock, cid) {
             fail_point!("txn_scheduler_acquire_success");
             tctx.on_schedule();
@@ -392,7 +468,6 @@ impl<E: Engine, L: LockManager> Scheduler<E, L> {
                         sched
                             .get_sched_pool(task.cmd.priority())
                             .clone()
-                            .pool
                             .spawn(async move {
                                 sched.finish_with_err(task.cid, Error::from(err));
                             })
@@ -427,12 +502,39 @@ impl<E: Engine, L: LockManager> Scheduler<E, L> {
         }
     }
 
+    /// Feeds this leg's write-byte cost into the resource group's virtual
+    /// time, without releasing its admission slot -- used both when a
+    /// command is done for good (via `finish_admission`, which additionally
+    /// releases) and when it's about to continue as a `NextCommand`, whose
+    /// slot is carried forward instead of released.
+    fn consume_admission(&self, tctx: &TaskContext) {
+        if let (Some(group), Some(resource_ctl)) =
+            (&tctx.admission_group, &self.inner.resource_ctl)
+        {
+            resource_ctl.consume(group, ResourceConsumeType::IoBytes, tctx.write_bytes as u64);
+        }
+    }
+
+    /// Like `consume_admission`, but additionally releases the admission
+    /// slot `AdmissionController::admit` reserved back in `run_cmd`. Call
+    /// this once a command's whole chain -- including every `NextCommand`
+    /// hop -- is truly done, never partway through it.
+    fn finish_admission(&self, tctx: &mut TaskContext) {
+        self.consume_admission(tctx);
+        if let Some(group) = tctx.admission_group.take() {
+            if let Some(admission) = &self.inner.admission {
+                admission.release(&group);
+            }
+        }
+    }
+
     /// Calls the callback with an error.
     fn finish_with_err(&self, cid: u64, err: Error) {
         debug!("write command finished with error"; "cid" => cid);
-        let tctx = self.inner.dequeue_task_context(cid);
+        let mut tctx = self.inner.dequeue_task_context(cid);
 
         SCHED_STAGE_COUNTER_VEC.get(tctx.tag).error.inc();
+        self.finish_admission(&mut tctx);
 
         let pr = ProcessResult::Failed {
             err: StorageError::from(err),
@@ -450,11 +552,14 @@ impl<E: Engine, L: LockManager> Scheduler<E, L> {
         SCHED_STAGE_COUNTER_VEC.get(tag).read_finish.inc();
 
         debug!("read command finished"; "cid" => cid);
-        let tctx = self.inner.dequeue_task_context(cid);
+        let mut tctx = self.inner.dequeue_task_context(cid);
         if let ProcessResult::NextCommand { cmd } = pr {
             SCHED_STAGE_COUNTER_VEC.get(tag).next_cmd.inc();
-            self.schedule_command(cmd, tctx.cb.unwrap());
+            self.consume_admission(&tctx);
+            let admission_group = tctx.admission_group.take();
+            self.schedule_command(cmd, tctx.cb.unwrap(), admission_group);
         } else {
+            self.finish_admission(&mut tctx);
             tctx.cb.unwrap().execute(pr);
         }
 
@@ -490,26 +595,30 @@ impl<E: Engine, L: LockManager> Scheduler<E, L> {
         debug!("write command finished";
             "cid" => cid, "pipelined" => pipelined, "async_apply_prewrite" => async_apply_prewrite);
         drop(lock_guards);
-        let tctx = self.inner.dequeue_task_context(cid);
+        let mut tctx = self.inner.dequeue_task_context(cid);
 
         // If pipelined pessimistic lock or async apply prewrite takes effect, it's not guaranteed
         // that the proposed or committed callback is surely invoked, which takes and invokes
         // `tctx.cb(tctx.pr)`.
-        if let Some(cb) = tctx.cb {
+        if let Some(cb) = tctx.cb.take() {
             let pr = match result {
-                Ok(()) => pr.or(tctx.pr).unwrap(),
+                Ok(()) => pr.or(tctx.pr.take()).unwrap(),
                 Err(e) => ProcessResult::Failed {
                     err: StorageError::from(e),
                 },
             };
             if let ProcessResult::NextCommand { cmd } = pr {
                 SCHED_STAGE_COUNTER_VEC.get(tag).next_cmd.inc();
-                self.schedule_command(cmd, cb);
+                self.consume_admission(&tctx);
+                let admission_group = tctx.admission_group.take();
+                self.schedule_command(cmd, cb, admission_group);
             } else {
+                self.finish_admission(&mut tctx);
                 cb.execute(pr);
             }
         } else {
             assert!(pipelined || async_apply_prewrite);
+            self.finish_admission(&mut tctx);
         }
 
         self.release_lock(&tctx.lock, cid);
@@ -526,8 +635,9 @@ impl<E: Engine, L: LockManager> Scheduler<E, L> {
         wait_timeout: Option<WaitTimeout>,
     ) {
         debug!("command waits for lock released"; "cid" => cid);
-        let tctx = self.inner.dequeue_task_context(cid);
+        let mut tctx = self.inner.dequeue_task_context(cid);
         SCHED_STAGE_COUNTER_VEC.get(tctx.tag).lock_wait.inc();
+        self.finish_admission(&mut tctx);
         self.inner.lock_mgr.wait_for(
             start_ts,
             tctx.cb.unwrap(),
@@ -558,7 +668,6 @@ impl<E: Engine, L: LockManager> Scheduler<E, L> {
 
         self.get_sched_pool(task.cmd.priority())
             .clone()
-            .pool
             .spawn(async move {
                 fail_point!("scheduler_async_snapshot_finish");
                 SCHED_STAGE_COUNTER_VEC.get(tag).process.inc();
@@ -721,7 +830,7 @@ impl<E: Engine, L: LockManager> Scheduler<E, L> {
                         };
 
                     let sched = scheduler.clone();
-                    let sched_pool = scheduler.get_sched_pool(priority).pool.clone();
+                    let sched_pool = scheduler.get_sched_pool(priority).clone();
                     // The callback to receive async results of write prepare from the storage engine.
                     let engine_cb = Box::new(move |(_, result)| {
                         sched_pool
@@ -799,6 +799,31 @@ fn test_node_learner_conf_change() {
     test_learner_conf_change(&mut cluster);
 }
 
+#[test]
+fn test_promote_learner_and_transfer_leader() {
+    let count = 3;
+    let mut cluster = new_node_cluster(0, count);
+    let pd_client = Arc::clone(&cluster.pd_client);
+    pd_client.disable_default_operator();
+    let r1 = cluster.run_conf_change();
+    cluster.must_put(b"k1", b"v1");
+
+    // Add learner (2, 2) to region 1.
+    pd_client.must_add_peer(r1, new_learner_peer(2, 2));
+    must_get_equal(&cluster.get_engine(2), b"k1", b"v1");
+
+    // Promote it and transfer leadership to it in one call, instead of the
+    // two separate PD-scheduled operators this replaces.
+    let resp = cluster
+        .promote_learner_and_transfer_leader(r1, new_learner_peer(2, 2))
+        .unwrap();
+    assert!(!resp.get_header().has_error(), "{:?}", resp);
+
+    pd_client.must_none_pending_peer(new_peer(2, 2));
+    cluster.reset_leader_of_region(r1);
+    pd_client.region_leader_must_be(r1, new_peer(2, 2));
+}
+
 #[test]
 fn test_learner_with_slow_snapshot() {
     let mut cluster = new_server_cluster(0, 3);